@@ -0,0 +1,156 @@
+//! What the fuzz targets check. `tests/fuzz_corpus.rs` replays the corpora through the same functions.
+#![allow(dead_code)] // Each target only uses its own.
+
+use rolling_looking_glass::codec::{
+    self, frame, get_bytes, get_varint, put_string, put_u16, put_varint,
+};
+use rolling_looking_glass::error::ConnectionError;
+use rolling_looking_glass::forwarding::{ForwardingConfig, ForwardingMode};
+use rolling_looking_glass::message::DEFAULT_DISCONNECT_MESSAGE;
+use rolling_looking_glass::responder::Responder;
+use rolling_looking_glass::status::{parse_component, StatusConfig};
+use rolling_looking_glass::timeout::Timeouts;
+use rolling_looking_glass::vhost::Profile;
+use std::time::Duration;
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::runtime::{Builder, Runtime};
+
+static PEER: &str = "192.0.2.1:50000";
+static HOSTNAME: &str = "localhost";
+static BUNGEECORD_HOSTNAME: &str = "localhost\0198.51.100.7\0069a79f444e94726a5befca90e38aaf5";
+static VELOCITY_SECRET: &[u8] = b"fuzz";
+static STATUS_PROTOCOL_NUMBER: i32 = 767i32;
+// 1.7.6, 1.13, 1.19, 1.19.3, 1.20.2 and 1.21.1: around where Velocity and the Login Start UUID came in.
+static LOGIN_PROTOCOL_NUMBERS: [i32; 6] = [5, 393, 759, 761, 764, 767];
+// The whole input is there from the start, so any wait at all means the responder hung.
+// The clock is paused, so a hang reaches the deadline as soon as the runtime idles, however slow the machine.
+static TIMEOUT: Duration = Duration::from_secs(1);
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap()
+}
+
+fn responder(mode: u8) -> Responder {
+    let mut responder = Responder::new(Profile {
+        status: StatusConfig::new(String::from("Fuzz")),
+        disconnect_message: parse_component(DEFAULT_DISCONNECT_MESSAGE),
+    });
+    responder.forwarding = ForwardingConfig {
+        mode: match mode % 3 {
+            0 => ForwardingMode::None,
+            1 => ForwardingMode::Bungeecord,
+            _ => ForwardingMode::Velocity,
+        },
+        velocity_secret: VELOCITY_SECRET.to_vec(),
+    };
+    responder.timeouts = Timeouts {
+        handshake: TIMEOUT,
+        status: TIMEOUT,
+        connection: TIMEOUT,
+        write: TIMEOUT,
+        ..Timeouts::default()
+    };
+    responder
+}
+
+fn handshake_packet(protocol_number: i32, hostname: &str, intent: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_varint(&mut body, protocol_number);
+    put_string(&mut body, hostname);
+    put_u16(&mut body, 25565);
+    put_varint(&mut body, intent);
+    frame(0x00, &body)
+}
+
+/// Plays `input` as everything the client sends, and returns what the server wrote back.
+fn exchange(responder: &Responder, input: &[u8]) -> (Result<(), ConnectionError>, Vec<u8>) {
+    runtime().block_on(async {
+        let peer = PEER.parse().unwrap();
+        let (mut client, server) = duplex(input.len() + 64 * 1024);
+        client.write_all(input).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut socket = BufReader::new(server);
+        let result = responder.respond(&mut socket, &peer).await;
+        drop(socket);
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        assert!(
+            !matches!(result, Err(ConnectionError::TimedOut(_))),
+            "{:?}",
+            result
+        );
+        (result, output)
+    })
+}
+
+/// Whatever the server wrote must be whole packets.
+fn assert_framed(output: &[u8]) {
+    let mut output = output;
+    while !output.is_empty() {
+        let length = get_varint(&mut output).unwrap();
+        get_bytes(&mut output, usize::try_from(length).unwrap()).unwrap();
+    }
+}
+
+/// The first byte picks the forwarding mode, the rest is everything the client sends.
+pub fn handshake(data: &[u8]) {
+    if let Some((mode, input)) = data.split_first() {
+        exchange(&responder(*mode), input);
+    }
+}
+
+/// The stream and buffer decoders must agree, and what they decode must survive a round trip.
+pub fn read_varint(data: &[u8]) {
+    let mut buf = data;
+    let decoded = get_varint(&mut buf);
+    let mut reader = data;
+    let streamed = runtime().block_on(codec::read_varint(&mut reader));
+    match (decoded, streamed) {
+        (Ok(decoded), Ok(streamed)) => {
+            assert_eq!(decoded, streamed);
+            assert_eq!(buf.len(), reader.len());
+            let mut encoded = Vec::new();
+            put_varint(&mut encoded, decoded);
+            assert!(encoded.len() <= data.len() - buf.len());
+            assert_eq!(get_varint(&mut encoded.as_slice()).unwrap(), decoded);
+        }
+        (Err(_), Err(_)) => {}
+        (decoded, streamed) => panic!(
+            "get_varint gave {:?} but read_varint gave {:?}",
+            decoded, streamed
+        ),
+    }
+}
+
+/// Everything a client sends after a status handshake.
+pub fn status_ping(data: &[u8]) {
+    let mut input = handshake_packet(STATUS_PROTOCOL_NUMBER, HOSTNAME, 1);
+    input.extend_from_slice(data);
+    let (_, output) = exchange(&responder(0), &input);
+    assert_framed(&output);
+}
+
+/// The first byte picks the protocol number, the second the forwarding mode,
+/// the rest is everything a client sends after a login handshake.
+pub fn login_start(data: &[u8]) {
+    let [protocol, mode, rest @ ..] = data else {
+        return;
+    };
+    let index = *protocol as usize % LOGIN_PROTOCOL_NUMBERS.len();
+    let protocol_number = LOGIN_PROTOCOL_NUMBERS[index];
+    let responder = responder(*mode);
+    let hostname = if responder.forwarding.mode == ForwardingMode::Bungeecord {
+        BUNGEECORD_HOSTNAME
+    } else {
+        HOSTNAME
+    };
+    let mut input = handshake_packet(protocol_number, hostname, 2);
+    input.extend_from_slice(rest);
+    let (_, output) = exchange(&responder, &input);
+    assert_framed(&output);
+}
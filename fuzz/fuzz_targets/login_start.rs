@@ -0,0 +1,7 @@
+#![no_main]
+
+mod harness;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| harness::login_start(data));
@@ -0,0 +1,243 @@
+use crate::cidr::Cidr;
+use crate::status::parse_component;
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+pub static DEFAULT_DENY_MESSAGE: &str = "You are not allowed to connect";
+pub static DEFAULT_MAINTENANCE_MESSAGE: &str = "Down for maintenance";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessActionKind {
+    /// Close the socket without writing anything.
+    #[default]
+    Drop,
+    /// Login attempts get the message as Disconnect (Login), status requests are dropped.
+    Disconnect,
+    /// Status requests get the message as MOTD, login attempts get it as Disconnect (Login).
+    Maintenance,
+}
+
+impl FromStr for AccessActionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(AccessActionKind::Drop),
+            "disconnect" => Ok(AccessActionKind::Disconnect),
+            "maintenance" => Ok(AccessActionKind::Maintenance),
+            _ => Err(format!(
+                "{} is not one of drop, disconnect or maintenance",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessAction {
+    Drop,
+    Disconnect(Value),
+    Maintenance(Value),
+}
+
+impl AccessAction {
+    pub fn new(kind: AccessActionKind, message: &str) -> Self {
+        match kind {
+            AccessActionKind::Drop => AccessAction::Drop,
+            AccessActionKind::Disconnect => AccessAction::Disconnect(parse_component(message)),
+            AccessActionKind::Maintenance => AccessAction::Maintenance(parse_component(message)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct AccessRule {
+    cidr: Cidr,
+    action: AccessAction,
+}
+
+/// Allowlist and denylist. Denied addresses get the action of the first matching rule, and when an allowlist
+/// is set, addresses missing from it get the not allowed action.
+#[derive(Clone, Debug)]
+pub struct AccessLists {
+    allow: Option<Vec<Cidr>>,
+    deny: Vec<AccessRule>,
+    not_allowed: AccessAction,
+}
+
+impl AccessLists {
+    pub fn new(not_allowed: AccessAction) -> Self {
+        AccessLists {
+            allow: None,
+            deny: Vec::new(),
+            not_allowed,
+        }
+    }
+
+    /// Returns `None` when the address is allowed.
+    pub fn check(&self, ip: &IpAddr) -> Option<&AccessAction> {
+        if let Some(rule) = self.deny.iter().find(|rule| rule.cidr.contains(ip)) {
+            return Some(&rule.action);
+        }
+        match &self.allow {
+            Some(allow) if !allow.iter().any(|cidr| cidr.contains(ip)) => Some(&self.not_allowed),
+            _ => None,
+        }
+    }
+
+    /// One IP or CIDR range per line. Blank lines and lines starting with `#` are skipped.
+    pub fn load_allowlist(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut allow = Vec::new();
+        for (cidr, _) in read_rules(path)? {
+            allow.push(Cidr::from_str(&cidr)?);
+        }
+        self.allow = Some(allow);
+        Ok(())
+    }
+
+    /// One `CIDR [ACTION [MESSAGE]]` per line, where the message is plain text or a JSON text component.
+    /// Rules without an action get `default`, and rules without a message get `default_message`.
+    pub fn load_denylist(
+        &mut self,
+        path: &Path,
+        default: AccessActionKind,
+        default_message: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut deny = Vec::new();
+        for (cidr, rest) in read_rules(path)? {
+            let (kind, message) = match rest.split_once(char::is_whitespace) {
+                Some((kind, message)) => (kind, message.trim()),
+                None => (rest.as_str(), ""),
+            };
+            let kind = if kind.is_empty() {
+                default
+            } else {
+                AccessActionKind::from_str(kind)?
+            };
+            let message = if message.is_empty() {
+                default_message
+            } else {
+                message
+            };
+            deny.push(AccessRule {
+                cidr: Cidr::from_str(&cidr)?,
+                action: AccessAction::new(kind, message),
+            });
+        }
+        self.deny = deny;
+        Ok(())
+    }
+}
+
+/// Splits every meaningful line into its address and whatever follows it.
+fn read_rules(path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(char::is_whitespace) {
+            Some((cidr, rest)) => (cidr.to_string(), rest.trim().to_string()),
+            None => (line.to_string(), String::new()),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A list file that is removed when dropped.
+    struct ListFile(PathBuf);
+
+    impl ListFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rolling_looking_glass_{}_{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::write(&path, content).unwrap();
+            ListFile(path)
+        }
+    }
+
+    impl Drop for ListFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn allowlist() {
+        let file = ListFile::new(
+            "allowlist",
+            "# Proxies\n\n  10.0.0.0/8  \n192.0.2.7 the monitoring host\n\t\n",
+        );
+        let mut lists = AccessLists::new(AccessAction::Drop);
+        assert_eq!(lists.check(&ip("203.0.113.9")), None);
+        lists.load_allowlist(&file.0).unwrap();
+        assert_eq!(lists.check(&ip("10.1.2.3")), None);
+        assert_eq!(lists.check(&ip("192.0.2.7")), None);
+        assert_eq!(lists.check(&ip("192.0.2.8")), Some(&AccessAction::Drop));
+    }
+
+    #[test]
+    fn denylist() {
+        let file = ListFile::new(
+            "denylist",
+            "# Scanners\n198.51.100.0/24\n\n203.0.113.0/24 disconnect\n\
+             203.0.113.9 maintenance Back soon\n192.0.2.0/24 disconnect {\"text\":\"Banned\",\"color\":\"red\"}\n",
+        );
+        let mut lists = AccessLists::new(AccessAction::Drop);
+        lists
+            .load_denylist(&file.0, AccessActionKind::Drop, "Denied")
+            .unwrap();
+        assert_eq!(lists.check(&ip("198.51.100.1")), Some(&AccessAction::Drop));
+        // The first matching rule wins.
+        assert_eq!(
+            lists.check(&ip("203.0.113.9")),
+            Some(&AccessAction::Disconnect(parse_component("Denied")))
+        );
+        assert_eq!(
+            lists.check(&ip("192.0.2.1")),
+            Some(&AccessAction::Disconnect(
+                serde_json::json!({ "text": "Banned", "color": "red" })
+            ))
+        );
+        assert_eq!(
+            lists.check(&ip("::ffff:198.51.100.1")),
+            Some(&AccessAction::Drop)
+        );
+        assert_eq!(lists.check(&ip("10.1.2.3")), None);
+    }
+
+    #[test]
+    fn bad_entries() {
+        let mut lists = AccessLists::new(AccessAction::Drop);
+        for content in [
+            "10.0.0.0/33\n",
+            "example.com\n",
+            "10.0.0.0/8\nnot an address\n",
+        ] {
+            let file = ListFile::new("bad_allowlist", content);
+            assert!(lists.load_allowlist(&file.0).is_err(), "{:?}", content);
+        }
+        let file = ListFile::new("bad_denylist", "10.0.0.0/8 ban\n");
+        assert!(lists
+            .load_denylist(&file.0, AccessActionKind::Drop, "Denied")
+            .is_err());
+        // A failed load keeps the rules that were there.
+        assert_eq!(lists.check(&ip("10.1.2.3")), None);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::error::ConnectionError;
+use crate::listener::Stream;
+use serde::Serialize;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::warn;
+
+/// Where and when the access log rotates. Zero disables a rotation trigger.
+#[derive(Clone, Debug)]
+pub struct AccessLogConfig {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub max_age: Duration,
+    /// Rotated files are kept as `PATH.1` (the newest) to `PATH.KEEP`.
+    pub keep: usize,
+}
+
+/// One line of the access log, filled in as the connection goes.
+#[derive(Debug, Serialize)]
+pub struct AccessRecord {
+    pub timestamp: String,
+    pub id: u64,
+    pub listener: String,
+    /// The client address, once the PROXY header or forwarding data replaced the peer address.
+    pub peer_ip: String,
+    pub peer_port: u16,
+    pub hostname: Option<String>,
+    pub server_port: Option<u16>,
+    pub protocol_number: Option<usize>,
+    pub intent: Option<&'static str>,
+    pub username: Option<String>,
+    /// `completed`, `rejected`, `disconnected`, `timeout` or `error`.
+    pub outcome: &'static str,
+    pub reason: Option<String>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration_ms: u64,
+}
+
+impl AccessRecord {
+    pub fn new(id: u64, listener: String, peer: &SocketAddr) -> Self {
+        AccessRecord {
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            id,
+            listener,
+            peer_ip: peer.ip().to_string(),
+            peer_port: peer.port(),
+            hostname: None,
+            server_port: None,
+            protocol_number: None,
+            intent: None,
+            username: None,
+            outcome: "completed",
+            reason: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            duration_ms: 0,
+        }
+    }
+
+    pub fn set_peer(&mut self, peer: &SocketAddr) {
+        self.peer_ip = peer.ip().to_string();
+        self.peer_port = peer.port();
+    }
+
+    /// Refused before the handshake, by a limit, the access lists or a penalty.
+    pub fn reject(&mut self, reason: String) {
+        self.outcome = "rejected";
+        self.reason = Some(reason);
+    }
+
+    pub fn fail(&mut self, error: &ConnectionError) {
+        self.outcome = match error {
+            ConnectionError::Disconnected => "disconnected",
+            ConnectionError::TimedOut(_) => "timeout",
+            _ => "error",
+        };
+        self.reason = Some(error.to_string());
+    }
+
+    pub fn finish(&mut self, stream: &Stream, started: Instant) {
+        self.bytes_in = stream.bytes_read();
+        self.bytes_out = stream.bytes_written();
+        self.duration_ms = started.elapsed().as_millis() as u64;
+    }
+}
+
+/// Writes records as JSON lines from a thread of its own, so connections never wait on the disk.
+pub struct AccessLog {
+    sender: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    pub fn open(config: &AccessLogConfig) -> Result<Self, Box<dyn Error>> {
+        let mut file = RotatingFile::open(config.clone())?;
+        let (sender, receiver) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            for line in receiver {
+                if let Err(e) = file.write_line(&line) {
+                    warn!("Unable to write the access log: {}", e);
+                }
+            }
+        });
+        Ok(AccessLog { sender })
+    }
+
+    pub fn write(&self, record: &AccessRecord) {
+        match serde_json::to_string(record) {
+            Ok(line) => {
+                let _ = self.sender.send(line);
+            }
+            Err(e) => warn!("Unable to encode an access log record: {}", e),
+        }
+    }
+}
+
+struct RotatingFile {
+    config: AccessLogConfig,
+    /// Only `None` while rotating, since Windows can't rename an open file.
+    writer: Option<BufWriter<File>>,
+    size: u64,
+    opened: SystemTime,
+}
+
+impl RotatingFile {
+    fn open(config: AccessLogConfig) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            config,
+            writer: Some(BufWriter::new(file)),
+            size,
+            opened: SystemTime::now(),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        // A failed rotation left the file closed, try again.
+        if self.writer.is_none() {
+            *self = RotatingFile::open(self.config.clone())?;
+        }
+        let length = line.len() as u64 + 1;
+        let too_large = self.config.max_bytes != 0
+            && self.size != 0
+            && self.size + length > self.config.max_bytes;
+        let too_old = !self.config.max_age.is_zero()
+            && self.opened.elapsed().unwrap_or_default() >= self.config.max_age;
+        if too_large || too_old {
+            self.rotate()?;
+        }
+        let writer = self.writer.as_mut().ok_or("Access log isn't open")?;
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            for index in (1..self.config.keep).rev() {
+                let from = rotated_path(path, index);
+                if from.exists() {
+                    std::fs::rename(&from, rotated_path(path, index + 1))?;
+                }
+            }
+            std::fs::rename(path, rotated_path(path, 1))?;
+        }
+        *self = RotatingFile::open(self.config.clone())?;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
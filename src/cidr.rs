@@ -0,0 +1,150 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IP network such as `10.0.0.0/8` or `2001:db8::/32`. A bare address is a single host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, String> {
+        let addr = addr.to_canonical();
+        let max_prefix = max_prefix(&addr);
+        if prefix > max_prefix {
+            return Err(format!("Prefix /{} is longer than /{}", prefix, max_prefix));
+        }
+        Ok(Cidr {
+            network: mask(&addr, prefix),
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        addr.is_ipv4() == self.network.is_ipv4() && mask(&addr, self.prefix) == self.network
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let written = IpAddr::from_str(addr.trim())
+            .map_err(|e| format!("{} is not a valid IP address: {}", addr, e))?;
+        let addr = written.to_canonical();
+        let prefix = match prefix {
+            Some(prefix) => {
+                let prefix = u8::from_str(prefix.trim())
+                    .map_err(|e| format!("{} is not a valid prefix length: {}", prefix, e))?;
+                // IPv4-mapped ranges such as ::ffff:10.0.0.0/104 are matched as the IPv4 range they map.
+                if written.is_ipv6() && addr.is_ipv4() {
+                    prefix
+                        .checked_sub(96)
+                        .ok_or_else(|| format!("{} is wider than the IPv4-mapped range", s))?
+                } else {
+                    prefix
+                }
+            }
+            None => max_prefix(&addr),
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Clears every bit of `addr` past the first `prefix` bits.
+pub fn mask(addr: &IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(*v4);
+            let mask = u32::MAX
+                .checked_shl(32 - prefix.min(32) as u32)
+                .unwrap_or(0);
+            IpAddr::V4((bits & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(*v6);
+            let mask = u128::MAX
+                .checked_shl(128 - prefix.min(128) as u32)
+                .unwrap_or(0);
+            IpAddr::V6((bits & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        Cidr::from_str(s).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn prefixes() {
+        assert!(cidr("0.0.0.0/0").contains(&ip("203.0.113.9")));
+        assert!(!cidr("0.0.0.0/0").contains(&ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(&ip("2001:db8::1")));
+        assert!(cidr("10.0.0.0/8").contains(&ip("10.255.255.255")));
+        assert!(!cidr("10.0.0.0/8").contains(&ip("11.0.0.0")));
+        assert!(cidr("192.0.2.7/32").contains(&ip("192.0.2.7")));
+        assert!(!cidr("192.0.2.7/32").contains(&ip("192.0.2.8")));
+        assert!(cidr("2001:db8::1/128").contains(&ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(&ip("2001:db8::2")));
+        assert!(cidr("2001:db8::/32").contains(&ip("2001:db8:ffff::1")));
+    }
+
+    #[test]
+    fn bare_addresses() {
+        assert_eq!(cidr("192.0.2.7"), cidr("192.0.2.7/32"));
+        assert_eq!(cidr("2001:db8::1"), cidr("2001:db8::1/128"));
+        // Host bits are cleared.
+        assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(mask(&ip("2001:db8::1"), 0), ip("::"));
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        assert!(cidr("10.0.0.0/8").contains(&ip("::ffff:10.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(&ip("10.1.2.3")));
+        assert_eq!(cidr("::ffff:192.0.2.7"), cidr("192.0.2.7/32"));
+        assert!(Cidr::from_str("::ffff:10.0.0.0/64").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "10.0.0.0/33",
+            "2001:db8::/129",
+            "10.0.0/8",
+            "10.0.0.0/",
+            "example.com",
+            "",
+        ] {
+            assert!(Cidr::from_str(s).is_err(), "{}", s);
+        }
+    }
+}
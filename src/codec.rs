@@ -0,0 +1,391 @@
+use crate::error::ConnectionError;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// https://minecraft.wiki/w/Java_Edition_protocol/Data_types
+static VARINT_MAX_BYTES: usize = 5usize;
+static VARLONG_MAX_BYTES: usize = 10usize;
+// A character takes at most 3 bytes in the modified UTF-8 the protocol's length limits were made for.
+static STRING_MAX_BYTES_PER_CHAR: usize = 3usize;
+
+/// Reads a VarInt off the stream, such as the length prefix of a packet.
+pub async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<i32, ConnectionError> {
+    let mut value = 0u32;
+    for i in 0..VARINT_MAX_BYTES {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(ConnectionError::InvalidVarInt)
+}
+
+pub fn get_bytes<'a>(buf: &mut &'a [u8], length: usize) -> Result<&'a [u8], ConnectionError> {
+    if buf.len() < length {
+        return Err(ConnectionError::Malformed("Packet too short"));
+    }
+    let (taken, rest) = buf.split_at(length);
+    *buf = rest;
+    Ok(taken)
+}
+
+pub fn get_u8(buf: &mut &[u8]) -> Result<u8, ConnectionError> {
+    Ok(get_bytes(buf, 1)?[0])
+}
+
+pub fn get_bool(buf: &mut &[u8]) -> Result<bool, ConnectionError> {
+    match get_u8(buf)? {
+        0x00 => Ok(false),
+        0x01 => Ok(true),
+        _ => Err(ConnectionError::Malformed("Not a valid boolean")),
+    }
+}
+
+pub fn get_u16(buf: &mut &[u8]) -> Result<u16, ConnectionError> {
+    let bytes = get_bytes(buf, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn get_i64(buf: &mut &[u8]) -> Result<i64, ConnectionError> {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(get_bytes(buf, 8)?);
+    Ok(i64::from_be_bytes(bytes))
+}
+
+/// UUIDs are sent as two big endian longs, most significant first.
+pub fn get_uuid(buf: &mut &[u8]) -> Result<u128, ConnectionError> {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(get_bytes(buf, 16)?);
+    Ok(u128::from_be_bytes(bytes))
+}
+
+pub fn get_varint(buf: &mut &[u8]) -> Result<i32, ConnectionError> {
+    let mut value = 0u32;
+    for i in 0..VARINT_MAX_BYTES {
+        let byte = get_u8(buf)?;
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(ConnectionError::InvalidVarInt)
+}
+
+pub fn get_varlong(buf: &mut &[u8]) -> Result<i64, ConnectionError> {
+    let mut value = 0u64;
+    for i in 0..VARLONG_MAX_BYTES {
+        let byte = get_u8(buf)?;
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i64);
+        }
+    }
+    Err(ConnectionError::InvalidVarInt)
+}
+
+/// Reads a string of at most `max_length` characters, counted in UTF-16 code units like the vanilla server does.
+/// `what` names the string in errors.
+pub fn get_string(
+    buf: &mut &[u8],
+    max_length: usize,
+    what: &'static str,
+) -> Result<String, ConnectionError> {
+    let length = get_varint(buf)?;
+    let length = usize::try_from(length).map_err(|_| ConnectionError::InvalidVarInt)?;
+    if length > max_length * STRING_MAX_BYTES_PER_CHAR {
+        return Err(ConnectionError::TooLong {
+            what,
+            length,
+            max: max_length * STRING_MAX_BYTES_PER_CHAR,
+        });
+    }
+    let string = std::str::from_utf8(get_bytes(buf, length)?)?;
+    let units = string.encode_utf16().count();
+    if units > max_length {
+        return Err(ConnectionError::TooLong {
+            what,
+            length: units,
+            max: max_length,
+        });
+    }
+    Ok(string.to_string())
+}
+
+/// The most bytes a string of at most `max_length` characters takes, length prefix included.
+pub fn string_max_size(max_length: usize) -> usize {
+    VARINT_MAX_BYTES + max_length * STRING_MAX_BYTES_PER_CHAR
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_i64(out: &mut Vec<u8>, value: i64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_uuid(out: &mut Vec<u8>, value: u128) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_varint(out: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            out.push(value as u8);
+            return;
+        }
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub fn put_varlong(out: &mut Vec<u8>, value: i64) {
+    let mut value = value as u64;
+    loop {
+        if value & !0x7F == 0 {
+            out.push(value as u8);
+            return;
+        }
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_varint(out, value.len() as i32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Prefixes a packet ID and body with their length.
+pub fn frame(packet_id: i32, body: &[u8]) -> Vec<u8> {
+    let mut id = Vec::with_capacity(VARINT_MAX_BYTES);
+    put_varint(&mut id, packet_id);
+    let mut packet = Vec::with_capacity(VARINT_MAX_BYTES + id.len() + body.len());
+    put_varint(&mut packet, (id.len() + body.len()) as i32);
+    packet.extend_from_slice(&id);
+    packet.extend_from_slice(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn varint(value: i32) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varint(&mut out, value);
+        out
+    }
+
+    fn varlong(value: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varlong(&mut out, value);
+        out
+    }
+
+    // https://minecraft.wiki/w/Java_Edition_protocol/Data_types#VarInt_and_VarLong
+    static VARINT_SAMPLES: [(i32, &[u8]); 11] = [
+        (0, &[0x00]),
+        (1, &[0x01]),
+        (2, &[0x02]),
+        (127, &[0x7F]),
+        (128, &[0x80, 0x01]),
+        (255, &[0xFF, 0x01]),
+        (25565, &[0xDD, 0xC7, 0x01]),
+        (2097151, &[0xFF, 0xFF, 0x7F]),
+        (2147483647, &[0xFF, 0xFF, 0xFF, 0xFF, 0x07]),
+        (-1, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]),
+        (-2147483648, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+    ];
+
+    static VARLONG_SAMPLES: [(i64, &[u8]); 7] = [
+        (0, &[0x00]),
+        (1, &[0x01]),
+        (127, &[0x7F]),
+        (128, &[0x80, 0x01]),
+        (
+            9223372036854775807,
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F],
+        ),
+        (
+            -1,
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+        ),
+        (
+            -9223372036854775808,
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+        ),
+    ];
+
+    #[test]
+    fn varint_samples() {
+        for (value, bytes) in VARINT_SAMPLES {
+            assert_eq!(varint(value), bytes, "encoding {}", value);
+            let mut buf = bytes;
+            assert_eq!(get_varint(&mut buf).unwrap(), value, "decoding {}", value);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn varlong_samples() {
+        for (value, bytes) in VARLONG_SAMPLES {
+            assert_eq!(varlong(value), bytes, "encoding {}", value);
+            let mut buf = bytes;
+            assert_eq!(get_varlong(&mut buf).unwrap(), value, "decoding {}", value);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn varint_rejects_a_sixth_byte() {
+        let mut buf: &[u8] = &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert!(matches!(
+            get_varint(&mut buf),
+            Err(ConnectionError::InvalidVarInt)
+        ));
+    }
+
+    #[test]
+    fn varlong_rejects_an_eleventh_byte() {
+        let mut buf: &[u8] = &[0x80; 11];
+        assert!(matches!(
+            get_varlong(&mut buf),
+            Err(ConnectionError::InvalidVarInt)
+        ));
+    }
+
+    #[test]
+    fn varint_truncated() {
+        let mut buf: &[u8] = &[0x80, 0x80];
+        assert!(matches!(
+            get_varint(&mut buf),
+            Err(ConnectionError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_varint_from_stream() {
+        for (value, bytes) in VARINT_SAMPLES {
+            let mut reader = bytes;
+            assert_eq!(read_varint(&mut reader).await.unwrap(), value);
+        }
+        let mut reader: &[u8] = &[0xFF; 6];
+        assert!(matches!(
+            read_varint(&mut reader).await,
+            Err(ConnectionError::InvalidVarInt)
+        ));
+        let mut reader: &[u8] = &[0xFF];
+        assert!(matches!(
+            read_varint(&mut reader).await,
+            Err(ConnectionError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn string_limits_count_utf16_units() {
+        let mut out = Vec::new();
+        put_string(&mut out, "\u{1F600}");
+        // One character, but two UTF-16 code units.
+        assert!(get_string(&mut out.as_slice(), 1, "Test").is_err());
+        assert_eq!(
+            get_string(&mut out.as_slice(), 2, "Test").unwrap(),
+            "\u{1F600}"
+        );
+    }
+
+    #[test]
+    fn string_rejects_oversized_prefix() {
+        let mut out = Vec::new();
+        put_varint(&mut out, 49);
+        out.extend_from_slice(&[b'a'; 49]);
+        assert!(matches!(
+            get_string(&mut out.as_slice(), 16, "Username"),
+            Err(ConnectionError::TooLong {
+                what: "Username",
+                length: 49,
+                max: 48
+            })
+        ));
+    }
+
+    #[test]
+    fn string_rejects_invalid_utf8() {
+        let mut buf: &[u8] = &[0x02, 0xC3, 0x28];
+        assert!(matches!(
+            get_string(&mut buf, 16, "Test"),
+            Err(ConnectionError::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn string_rejects_negative_length() {
+        let mut buf = varint(-1);
+        buf.push(b'a');
+        assert!(get_string(&mut buf.as_slice(), 16, "Test").is_err());
+    }
+
+    #[test]
+    fn uuid_round_trip() {
+        let uuid = 0x069a79f444e94726a5befca90e38aaf5u128;
+        let mut out = Vec::new();
+        put_uuid(&mut out, uuid);
+        assert_eq!(out.len(), 16);
+        assert_eq!(out[0], 0x06);
+        assert_eq!(get_uuid(&mut out.as_slice()).unwrap(), uuid);
+    }
+
+    #[test]
+    fn frame_prefixes_the_length() {
+        assert_eq!(frame(0x01, &[0xAA, 0xBB]), vec![0x03, 0x01, 0xAA, 0xBB]);
+        assert_eq!(frame(0x00, &[]), vec![0x01, 0x00]);
+    }
+
+    proptest! {
+        #[test]
+        fn varint_round_trip(value: i32) {
+            let bytes = varint(value);
+            prop_assert!(bytes.len() <= VARINT_MAX_BYTES);
+            let mut buf = bytes.as_slice();
+            prop_assert_eq!(get_varint(&mut buf).unwrap(), value);
+            prop_assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn varlong_round_trip(value: i64) {
+            let bytes = varlong(value);
+            prop_assert!(bytes.len() <= VARLONG_MAX_BYTES);
+            let mut buf = bytes.as_slice();
+            prop_assert_eq!(get_varlong(&mut buf).unwrap(), value);
+            prop_assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn string_round_trip(value in "\\PC{0,64}") {
+            let mut out = Vec::new();
+            put_string(&mut out, &value);
+            let units = value.encode_utf16().count();
+            prop_assert_eq!(get_string(&mut out.as_slice(), units, "Test").unwrap(), value);
+        }
+
+        #[test]
+        fn u16_and_i64_round_trip(short: u16, long: i64) {
+            let mut out = Vec::new();
+            put_u16(&mut out, short);
+            put_i64(&mut out, long);
+            let mut buf = out.as_slice();
+            prop_assert_eq!(get_u16(&mut buf).unwrap(), short);
+            prop_assert_eq!(get_i64(&mut buf).unwrap(), long);
+        }
+
+        #[test]
+        fn decoders_never_panic(bytes: Vec<u8>) {
+            let _ = get_varint(&mut bytes.as_slice());
+            let _ = get_varlong(&mut bytes.as_slice());
+            let _ = get_string(&mut bytes.as_slice(), 255, "Test");
+            let _ = get_uuid(&mut bytes.as_slice());
+        }
+    }
+}
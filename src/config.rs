@@ -0,0 +1,747 @@
+use crate::access::{
+    AccessAction, AccessActionKind, AccessLists, DEFAULT_DENY_MESSAGE, DEFAULT_MAINTENANCE_MESSAGE,
+};
+use crate::access_log::AccessLogConfig;
+use crate::cidr::Cidr;
+use crate::forwarding::{ForwardingConfig, ForwardingMode};
+use crate::handler::DefaultHandler;
+use crate::limit::{LimitAction, Limits};
+use crate::listener::{ListenAddress, ListenerConfig, DEFAULT_PLACEHOLDER_ADDRESS};
+use crate::message::DEFAULT_DISCONNECT_MESSAGE;
+use crate::proxy::ProxyConfig;
+use crate::responder::Responder;
+use crate::status::{load_favicon, parse_component, parse_sample_player, ShowIp, StatusConfig};
+use crate::timeout::Timeouts;
+use crate::version::VersionPolicy;
+use crate::vhost::{Profile, VirtualHostEntry, VirtualHosts};
+use serde::Deserialize;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+pub static DEFAULT_ADDRESS: &str = "127.0.0.1:25565";
+pub static DEFAULT_BRAND: &str = "Void";
+static WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The TOML config file. Every field is optional and defaults to the matching command line default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConfigFile {
+    pub address: String,
+    /// Replaces `address` when present.
+    pub listener: Vec<ListenerSection>,
+    pub logging: LoggingSection,
+    pub status: StatusSection,
+    pub messages: MessagesSection,
+    pub proxy_protocol: ProxyProtocolSection,
+    pub forwarding: ForwardingSection,
+    pub versions: VersionsSection,
+    pub virtual_hosts_file: Option<PathBuf>,
+    pub virtual_host: Vec<VirtualHostEntry>,
+    pub timeouts: TimeoutsSection,
+    pub limits: LimitsSection,
+    pub access: AccessSection,
+    pub metrics: MetricsSection,
+    pub access_log: AccessLogSection,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            address: DEFAULT_ADDRESS.to_string(),
+            listener: Vec::new(),
+            logging: LoggingSection::default(),
+            status: StatusSection::default(),
+            messages: MessagesSection::default(),
+            proxy_protocol: ProxyProtocolSection::default(),
+            forwarding: ForwardingSection::default(),
+            versions: VersionsSection::default(),
+            virtual_hosts_file: None,
+            virtual_host: Vec::new(),
+            timeouts: TimeoutsSection::default(),
+            limits: LimitsSection::default(),
+            access: AccessSection::default(),
+            metrics: MetricsSection::default(),
+            access_log: AccessLogSection::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerSection {
+    /// `host:port`, or `unix:/path/to/socket`.
+    pub address: String,
+    /// Defaults to dual-stack, unless another listener binds IPv4 on the same port.
+    pub dual_stack: Option<bool>,
+    /// Peer address of Unix socket connections. With the PROXY protocol, only LOCAL and UNKNOWN headers keep it.
+    pub placeholder_address: Option<String>,
+    /// Overrides `[proxy_protocol] enabled` for this listener.
+    pub proxy_protocol: Option<bool>,
+    pub brand: Option<String>,
+    pub motd: Option<String>,
+    pub disconnect_message: Option<String>,
+}
+
+impl ListenerSection {
+    pub fn new(address: String) -> Self {
+        ListenerSection {
+            address,
+            dual_stack: None,
+            placeholder_address: None,
+            proxy_protocol: None,
+            brand: None,
+            motd: None,
+            disconnect_message: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct LoggingSection {
+    /// `tracing` filter directives such as `info` or `rolling_looking_glass=debug`.
+    /// Falls back to `RUST_LOG`, then `info`.
+    pub level: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct StatusSection {
+    pub brand: String,
+    pub motd: String,
+    pub max_players: usize,
+    pub online_players: usize,
+    pub sample_players: Vec<String>,
+    pub favicon: Option<PathBuf>,
+    pub enforces_secure_chat: bool,
+    pub show_ip: ShowIp,
+}
+
+impl Default for StatusSection {
+    fn default() -> Self {
+        StatusSection {
+            brand: DEFAULT_BRAND.to_string(),
+            motd: String::new(),
+            max_players: 0,
+            online_players: 0,
+            sample_players: Vec::new(),
+            favicon: None,
+            enforces_secure_chat: false,
+            show_ip: ShowIp::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct MessagesSection {
+    pub disconnect: String,
+}
+
+impl Default for MessagesSection {
+    fn default() -> Self {
+        MessagesSection {
+            disconnect: DEFAULT_DISCONNECT_MESSAGE.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ProxyProtocolSection {
+    /// The default of every listener.
+    pub enabled: bool,
+    /// Only needed by TCP listeners, Unix socket peers are always trusted.
+    pub trusted: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ForwardingSection {
+    pub mode: ForwardingMode,
+    pub velocity_secret_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct VersionsSection {
+    /// Protocol numbers outside the window only get told which versions work.
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    pub accept_unknown: bool,
+    /// How the supported versions are named to the others, such as `1.8 to 1.21`.
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct TimeoutsSection {
+    pub handshake_ms: u64,
+    pub status_ms: u64,
+    pub connection_ms: u64,
+    pub write_ms: u64,
+    pub penalty_ms: u64,
+    /// How long active connections get to finish after SIGTERM or SIGINT. Zero closes them right away.
+    pub shutdown_grace_ms: u64,
+}
+
+impl From<&TimeoutsSection> for Timeouts {
+    fn from(section: &TimeoutsSection) -> Self {
+        Timeouts {
+            handshake: Duration::from_millis(section.handshake_ms),
+            status: Duration::from_millis(section.status_ms),
+            connection: Duration::from_millis(section.connection_ms),
+            write: Duration::from_millis(section.write_ms),
+            penalty: Duration::from_millis(section.penalty_ms),
+            shutdown_grace: Duration::from_millis(section.shutdown_grace_ms),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::from(&TimeoutsSection::default())
+    }
+}
+
+impl Default for TimeoutsSection {
+    fn default() -> Self {
+        TimeoutsSection {
+            handshake_ms: 5000,
+            status_ms: 10000,
+            connection_ms: 30000,
+            write_ms: 5000,
+            penalty_ms: 0,
+            shutdown_grace_ms: 10000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct LimitsSection {
+    /// New connections per second and per source, 0 disables the rate limit.
+    pub rate_per_second: f64,
+    pub burst: u32,
+    pub per_ip_connections: usize,
+    pub global_connections: usize,
+    /// IPv6 sources are aggregated by this prefix length.
+    pub ipv6_prefix: u8,
+    pub action: LimitAction,
+    pub message: String,
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        LimitsSection {
+            rate_per_second: 0.0,
+            burst: 10,
+            per_ip_connections: 0,
+            global_connections: 0,
+            ipv6_prefix: 64,
+            action: LimitAction::Close,
+            message: String::from("Slow down"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct MetricsSection {
+    /// Serves Prometheus metrics on `http://ADDRESS/metrics`. Changing it requires a restart.
+    pub address: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AccessLogSection {
+    /// Writes one JSON line per connection to this file. Changing it requires a restart.
+    pub path: Option<PathBuf>,
+    /// Rotates once the file would grow past this size, 0 disables.
+    pub max_bytes: u64,
+    /// Rotates once the file is this old, 0 disables.
+    pub max_age_secs: u64,
+    pub keep: usize,
+}
+
+impl Default for AccessLogSection {
+    fn default() -> Self {
+        AccessLogSection {
+            path: None,
+            max_bytes: 100 * 1024 * 1024,
+            max_age_secs: 0,
+            keep: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AccessSection {
+    pub allowlist: Option<PathBuf>,
+    pub denylist: Option<PathBuf>,
+    /// For denylist rules without an action of their own.
+    pub deny_action: AccessActionKind,
+    pub deny_message: String,
+    /// For addresses missing from the allowlist.
+    pub not_allowed_action: AccessActionKind,
+    pub not_allowed_message: String,
+}
+
+impl Default for AccessSection {
+    fn default() -> Self {
+        AccessSection {
+            allowlist: None,
+            denylist: None,
+            deny_action: AccessActionKind::Drop,
+            deny_message: DEFAULT_DENY_MESSAGE.to_string(),
+            not_allowed_action: AccessActionKind::Drop,
+            not_allowed_message: DEFAULT_MAINTENANCE_MESSAGE.to_string(),
+        }
+    }
+}
+
+/// A validated snapshot of the settings. Connections keep the snapshot they started with.
+pub struct Config {
+    pub listeners: Vec<ListenerConfig>,
+    pub logging: LoggingSection,
+    pub virtual_hosts_file: Option<PathBuf>,
+    pub forwarding: ForwardingConfig,
+    pub proxy: ProxyConfig,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub access: AccessLists,
+    pub metrics_address: Option<String>,
+    pub access_log: Option<AccessLogConfig>,
+    access_files: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn build(file: &ConfigFile) -> Result<Self, Box<dyn Error>> {
+        let section = &file.status;
+        let mut status = StatusConfig::new(section.brand.clone());
+        status.description = parse_component(&section.motd);
+        status.max_players = section.max_players;
+        status.online_players = section.online_players;
+        status.sample = section
+            .sample_players
+            .iter()
+            .map(|player| parse_sample_player(player))
+            .collect::<Result<Vec<_>, _>>()?;
+        status.enforces_secure_chat = section.enforces_secure_chat;
+        status.show_ip = section.show_ip;
+        if let Some(path) = &section.favicon {
+            status.favicon = Some(load_favicon(path)?);
+        }
+
+        let default_profile = Profile {
+            status,
+            disconnect_message: parse_component(&file.messages.disconnect),
+        };
+
+        let velocity_secret = match (file.forwarding.mode, &file.forwarding.velocity_secret_file) {
+            (ForwardingMode::Velocity, Some(path)) => {
+                let secret = std::fs::read_to_string(path)?;
+                // HMAC takes an empty key, which anyone could sign player info with.
+                if secret.trim().is_empty() {
+                    return Err(Box::from(format!(
+                        "The Velocity secret in {} is empty",
+                        path.display()
+                    )));
+                }
+                secret.trim().into()
+            }
+            (ForwardingMode::Velocity, None) => {
+                return Err(Box::from(
+                    "Velocity forwarding requires a velocity_secret_file",
+                ));
+            }
+            _ => Vec::new(),
+        };
+        let forwarding = ForwardingConfig {
+            mode: file.forwarding.mode,
+            velocity_secret,
+        };
+
+        let section = &file.timeouts;
+        if section.handshake_ms == 0
+            || section.status_ms == 0
+            || section.connection_ms == 0
+            || section.write_ms == 0
+        {
+            return Err(Box::from("Timeouts must be longer than 0 ms"));
+        }
+        let timeouts = Timeouts::from(section);
+
+        let section = &file.versions;
+        if let (Some(min), Some(max)) = (section.min, section.max) {
+            if min > max {
+                return Err(Box::from(
+                    "The minimum protocol number must not exceed the maximum",
+                ));
+            }
+        }
+        let versions = VersionPolicy {
+            min: section.min,
+            max: section.max,
+            accept_unknown: section.accept_unknown,
+            name: section.name.clone(),
+        };
+
+        let sections = if file.listener.is_empty() {
+            vec![ListenerSection::new(file.address.clone())]
+        } else {
+            file.listener.clone()
+        };
+        let addresses = sections
+            .iter()
+            .map(|section| ListenAddress::parse(&section.address))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut listeners = Vec::new();
+        for (section, address) in sections.iter().zip(&addresses) {
+            if listeners
+                .iter()
+                .any(|listener: &ListenerConfig| &listener.address == address)
+            {
+                return Err(Box::from(format!("{} is listed twice", address)));
+            }
+            // An IPv6 wildcard listener can't share its port with an IPv4 one unless it is IPv6 only.
+            let dual_stack = section.dual_stack.unwrap_or_else(|| match address {
+                ListenAddress::Tcp(addr) => !addresses.iter().any(|other| match other {
+                    ListenAddress::Tcp(other) => other.is_ipv4() && other.port() == addr.port(),
+                    ListenAddress::Unix(_) => false,
+                }),
+                ListenAddress::Unix(_) => false,
+            });
+            let placeholder = SocketAddr::from_str(
+                section
+                    .placeholder_address
+                    .as_deref()
+                    .unwrap_or(DEFAULT_PLACEHOLDER_ADDRESS),
+            )?;
+
+            // Virtual hosts fall back to the listener's profile, which falls back to the default one.
+            let mut profile = default_profile.clone();
+            if let Some(brand) = &section.brand {
+                profile.status.brand = brand.clone();
+            }
+            if let Some(motd) = &section.motd {
+                profile.status.description = parse_component(motd);
+            }
+            if let Some(disconnect_message) = &section.disconnect_message {
+                profile.disconnect_message = parse_component(disconnect_message);
+            }
+            let mut virtual_hosts = VirtualHosts::new(profile);
+            if let Some(path) = &file.virtual_hosts_file {
+                virtual_hosts.load(path)?;
+            }
+            for entry in &file.virtual_host {
+                virtual_hosts.add(entry.clone())?;
+            }
+
+            listeners.push(ListenerConfig {
+                address: address.clone(),
+                dual_stack,
+                placeholder,
+                proxy_protocol: section
+                    .proxy_protocol
+                    .unwrap_or(file.proxy_protocol.enabled),
+                responder: Responder {
+                    virtual_hosts,
+                    forwarding: forwarding.clone(),
+                    timeouts,
+                    versions: versions.clone(),
+                    handler: DefaultHandler,
+                },
+            });
+        }
+
+        let proxy = ProxyConfig {
+            trusted: file
+                .proxy_protocol
+                .trusted
+                .iter()
+                .map(|cidr| Cidr::from_str(cidr))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        for listener in &listeners {
+            if listener.proxy_protocol
+                && proxy.trusted.is_empty()
+                && matches!(listener.address, ListenAddress::Tcp(_))
+            {
+                return Err(Box::from(format!(
+                    "The PROXY protocol on {} needs at least one trusted IP or CIDR range",
+                    listener.address
+                )));
+            }
+        }
+
+        let section = &file.limits;
+        if !section.rate_per_second.is_finite() || section.rate_per_second < 0.0 {
+            return Err(Box::from("The rate limit must be a positive number"));
+        }
+        if section.ipv6_prefix > 128 {
+            return Err(Box::from("The IPv6 prefix must be at most /128"));
+        }
+        let limits = Limits {
+            rate_per_second: section.rate_per_second,
+            burst: section.burst as f64,
+            per_ip_connections: section.per_ip_connections,
+            global_connections: section.global_connections,
+            ipv6_prefix: section.ipv6_prefix,
+            action: section.action,
+            message: parse_component(&section.message),
+        };
+
+        let section = &file.access;
+        let mut access = AccessLists::new(AccessAction::new(
+            section.not_allowed_action,
+            &section.not_allowed_message,
+        ));
+        if let Some(path) = &section.allowlist {
+            access.load_allowlist(path)?;
+        }
+        if let Some(path) = &section.denylist {
+            access.load_denylist(path, section.deny_action, &section.deny_message)?;
+        }
+        let access_files = section
+            .allowlist
+            .iter()
+            .chain(section.denylist.iter())
+            .cloned()
+            .collect();
+
+        if let Some(level) = &file.logging.level {
+            EnvFilter::builder().parse(level)?;
+        }
+
+        Ok(Config {
+            listeners,
+            logging: file.logging.clone(),
+            virtual_hosts_file: file.virtual_hosts_file.clone(),
+            forwarding,
+            proxy,
+            timeouts,
+            limits,
+            access,
+            metrics_address: file.metrics.address.clone(),
+            access_log: file.access_log.path.as_ref().map(|path| AccessLogConfig {
+                path: path.clone(),
+                max_bytes: file.access_log.max_bytes,
+                max_age: Duration::from_secs(file.access_log.max_age_secs),
+                keep: file.access_log.keep,
+            }),
+            access_files,
+        })
+    }
+
+    pub fn log_filter(&self) -> EnvFilter {
+        let builder = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into());
+        match &self.logging.level {
+            Some(level) => builder.parse_lossy(level),
+            None => builder.from_env_lossy(),
+        }
+    }
+
+    /// Listeners are matched by address, so a reload can't hand a connection another listener's settings.
+    /// Listeners added since startup aren't bound, and ones removed fall back to the first listener.
+    pub fn listener(&self, address: &ListenAddress) -> &ListenerConfig {
+        self.listeners
+            .iter()
+            .find(|listener| &listener.address == address)
+            .unwrap_or(&self.listeners[0])
+    }
+
+    pub fn log_summary(&self) {
+        for listener in &self.listeners {
+            info!(
+                "Brand name on {}: {}",
+                &listener.address,
+                &listener
+                    .responder
+                    .virtual_hosts
+                    .default_profile()
+                    .status
+                    .brand
+            );
+        }
+        for listener in &self.listeners {
+            if listener.proxy_protocol {
+                info!("PROXY protocol enabled on {}", &listener.address);
+            }
+        }
+        if self.forwarding.mode != ForwardingMode::None {
+            info!("Client address forwarding: {:?}", self.forwarding.mode);
+        }
+    }
+}
+
+/// Where the settings come from. Files referenced by the settings are read again on every reload.
+pub enum ConfigSource {
+    File(PathBuf),
+    Arguments(Box<ConfigFile>),
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config, Box<dyn Error>> {
+        match self {
+            ConfigSource::File(path) => {
+                let file: ConfigFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+                Config::build(&file)
+            }
+            ConfigSource::Arguments(file) => Config::build(file),
+        }
+    }
+
+    fn watched_files(&self, config: &Config) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if let ConfigSource::File(path) = self {
+            files.push(path.clone());
+        }
+        if let Some(path) = &config.virtual_hosts_file {
+            files.push(path.clone());
+        }
+        files.extend(config.access_files.iter().cloned());
+        files
+    }
+}
+
+/// Swaps in a new config on SIGHUP or when a watched file changes.
+/// A config that fails validation is logged and the current one is kept.
+pub async fn watch_config(
+    source: ConfigSource,
+    sender: watch::Sender<Arc<Config>>,
+    log_filter: reload::Handle<EnvFilter, Registry>,
+) {
+    let mut modified = last_modified(&source.watched_files(&sender.borrow()));
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("Unable to listen for SIGHUP: {}", e);
+            None
+        }
+    };
+    loop {
+        #[cfg(unix)]
+        let reason = tokio::select! {
+            _ = interval.tick() => None,
+            Some(_) = async { hangup.as_mut()?.recv().await } => Some("SIGHUP"),
+        };
+        #[cfg(not(unix))]
+        let reason = {
+            interval.tick().await;
+            None
+        };
+        let reason = match reason {
+            Some(reason) => reason,
+            None => {
+                let current = last_modified(&source.watched_files(&sender.borrow()));
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                "file change"
+            }
+        };
+
+        info!("Reloading config after {}", reason);
+        match source.load() {
+            Ok(config) => {
+                let moved = {
+                    let current = &sender.borrow().listeners;
+                    config.listeners.len() != current.len()
+                        || config
+                            .listeners
+                            .iter()
+                            .zip(current)
+                            .any(|(new, old)| new.address != old.address)
+                };
+                if moved {
+                    warn!("Changing the listening addresses requires a restart");
+                }
+                if let Err(e) = log_filter.reload(config.log_filter()) {
+                    warn!("Unable to reload the log filter: {}", e);
+                }
+                config.log_summary();
+                modified = last_modified(&source.watched_files(&config));
+                sender.send_replace(Arc::new(config));
+                info!("Config reloaded");
+            }
+            Err(e) => warn!("Config reload failed, keeping the current config: {}", e),
+        }
+    }
+}
+
+fn last_modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|path| modified_time(path)).collect()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxied(addresses: &[&str], enabled: bool, trusted: &[&str]) -> ConfigFile {
+        ConfigFile {
+            listener: addresses
+                .iter()
+                .map(|address| ListenerSection::new(address.to_string()))
+                .collect(),
+            proxy_protocol: ProxyProtocolSection {
+                enabled,
+                trusted: trusted.iter().map(|cidr| cidr.to_string()).collect(),
+            },
+            ..ConfigFile::default()
+        }
+    }
+
+    #[test]
+    fn empty_velocity_secret() {
+        let path = std::env::temp_dir().join(format!(
+            "rolling_looking_glass_empty_secret_{}",
+            std::process::id()
+        ));
+        std::fs::write(&path, " \r\n").unwrap();
+        let file = ConfigFile {
+            forwarding: ForwardingSection {
+                mode: ForwardingMode::Velocity,
+                velocity_secret_file: Some(path.clone()),
+            },
+            ..ConfigFile::default()
+        };
+        let result = Config::build(&file);
+        std::fs::write(&path, "secret\n").unwrap();
+        let config = Config::build(&file);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert_eq!(config.unwrap().forwarding.velocity_secret, b"secret");
+    }
+
+    #[test]
+    fn proxy_protocol_trust() {
+        assert!(Config::build(&proxied(&["127.0.0.1:0"], true, &[])).is_err());
+        assert!(Config::build(&proxied(&["127.0.0.1:0"], true, &["10.0.0.0/8"])).is_ok());
+        assert!(Config::build(&proxied(&["unix:/tmp/glass.sock"], true, &[])).is_ok());
+
+        let mut file = proxied(&["127.0.0.1:0", "unix:/tmp/glass.sock"], false, &[]);
+        file.listener[1].proxy_protocol = Some(true);
+        let config = Config::build(&file).unwrap();
+        assert!(!config.listeners[0].proxy_protocol);
+        assert!(config.listeners[1].proxy_protocol);
+        file.listener[0].proxy_protocol = Some(true);
+        assert!(Config::build(&file).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::protocol::State;
+use std::fmt;
+use std::io;
+use std::str::Utf8Error;
+use std::string::{FromUtf16Error, FromUtf8Error};
+
+/// Why a connection ended before it was done. Protocol violations carry the offending values.
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// The client closed or reset the connection. Scanners and impatient clients do it all the time.
+    Disconnected,
+    Io(io::Error),
+    /// A deadline expired during the named phase.
+    TimedOut(&'static str),
+    /// Refused by the access lists after the handshake.
+    Denied,
+    Proxy(&'static str),
+    InvalidVarInt,
+    TooLong {
+        what: &'static str,
+        length: usize,
+        max: usize,
+    },
+    UnknownPacketId {
+        packet: &'static str,
+        id: i32,
+    },
+    /// A packet that exists, but not in this state or not at this point.
+    UnexpectedPacket {
+        state: State,
+        packet: &'static str,
+    },
+    InvalidTransition {
+        from: State,
+        to: State,
+    },
+    UnknownProtocol(i32),
+    InvalidIntent(i32),
+    TransferUnsupported(usize),
+    InvalidUsernameLength(usize),
+    InvalidUtf8,
+    Malformed(&'static str),
+    Forwarding(&'static str),
+    VelocityUnsupported(usize),
+}
+
+impl ConnectionError {
+    /// Nothing went wrong on our side, so it isn't worth more than a debug line.
+    pub fn is_benign(&self) -> bool {
+        matches!(self, ConnectionError::Disconnected)
+    }
+
+    /// The kind of error without the offending values, so it can label metrics.
+    pub fn reason(&self) -> String {
+        match self {
+            ConnectionError::Disconnected => String::from("Client disconnected"),
+            ConnectionError::Io(_) => String::from("I/O error"),
+            ConnectionError::TimedOut(phase) => format!("{} timed out", phase),
+            ConnectionError::Denied => String::from("Denied by the access lists"),
+            ConnectionError::Proxy(reason)
+            | ConnectionError::Malformed(reason)
+            | ConnectionError::Forwarding(reason) => reason.to_string(),
+            ConnectionError::InvalidVarInt => String::from("Not a valid VarInt"),
+            ConnectionError::TooLong { what, .. } => format!("{} too long", what),
+            ConnectionError::UnknownPacketId { .. } => String::from("Unknown packet id"),
+            ConnectionError::UnexpectedPacket { .. } => String::from("Unexpected packet"),
+            ConnectionError::InvalidTransition { .. } => String::from("Invalid state transition"),
+            ConnectionError::UnknownProtocol(_) => String::from("Unknown protocol number"),
+            ConnectionError::InvalidIntent(_) => String::from("Unknown intent"),
+            ConnectionError::TransferUnsupported(_) => {
+                String::from("Transfers not supported by protocol number")
+            }
+            ConnectionError::InvalidUsernameLength(_) => String::from("Illegal username length"),
+            ConnectionError::InvalidUtf8 => String::from("Invalid UTF-8"),
+            ConnectionError::VelocityUnsupported(_) => {
+                String::from("Velocity forwarding not supported by protocol number")
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Io(e) => write!(f, "I/O error: {}", e),
+            ConnectionError::TooLong { what, length, max } => {
+                write!(f, "{} too long ({} > {})", what, length, max)
+            }
+            ConnectionError::UnknownPacketId { packet, id } => {
+                write!(f, "Unknown packet id {:#04x} for {}", id, packet)
+            }
+            ConnectionError::UnexpectedPacket { state, packet } => {
+                write!(f, "Unexpected {} in the {} state", packet, state)
+            }
+            ConnectionError::InvalidTransition { from, to } => {
+                write!(
+                    f,
+                    "Invalid transition from the {} to the {} state",
+                    from, to
+                )
+            }
+            ConnectionError::UnknownProtocol(protocol_number)
+            | ConnectionError::TransferUnsupported(protocol_number)
+            | ConnectionError::VelocityUnsupported(protocol_number) => {
+                write!(f, "{} {}", self.reason(), protocol_number)
+            }
+            ConnectionError::InvalidIntent(intent) => write!(f, "Unknown intent {}", intent),
+            ConnectionError::InvalidUsernameLength(length) => {
+                write!(f, "Illegal username length {}", length)
+            }
+            _ => write!(f, "{}", self.reason()),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => ConnectionError::Disconnected,
+            _ => ConnectionError::Io(e),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for ConnectionError {
+    fn from(_: FromUtf8Error) -> Self {
+        ConnectionError::InvalidUtf8
+    }
+}
+
+impl From<FromUtf16Error> for ConnectionError {
+    fn from(_: FromUtf16Error) -> Self {
+        ConnectionError::InvalidUtf8
+    }
+}
+
+impl From<Utf8Error> for ConnectionError {
+    fn from(_: Utf8Error) -> Self {
+        ConnectionError::InvalidUtf8
+    }
+}
@@ -0,0 +1,268 @@
+use crate::codec::{get_bytes, get_string, get_varint};
+use crate::error::ConnectionError;
+use crate::listener::AsyncStream;
+use crate::protocol::{LoginClientbound, LoginServerbound, Protocol};
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+// https://github.com/SpigotMC/BungeeCord/blob/master/proxy/src/main/java/net/md_5/bungee/ServerConnector.java
+pub static BUNGEECORD_SERVER_ADDRESS_MAX_LENGTH: usize = 32767usize;
+// https://github.com/PaperMC/Velocity/blob/dev/3.0.0/proxy/src/main/java/com/velocitypowered/proxy/connection/VelocityConstants.java
+static VELOCITY_CHANNEL: &str = "velocity:player_info";
+static VELOCITY_MODERN_DEFAULT: u8 = 1u8;
+static VELOCITY_MESSAGE_ID: i32 = 0i32;
+static VELOCITY_SIGNATURE_LENGTH: usize = 32usize;
+// Login plugin messages were introduced in 1.13.
+pub static VELOCITY_MIN_PROTOCOL_NUMBER: usize = 393usize;
+static FORWARDED_ADDRESS_MAX_LENGTH: usize = 255usize;
+
+/// How a proxy in front of this service forwards the real client address.
+/// Only enable forwarding when the port can't be reached without going through the proxy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardingMode {
+    #[default]
+    None,
+    Bungeecord,
+    Velocity,
+}
+
+#[derive(Clone, Default)]
+pub struct ForwardingConfig {
+    pub mode: ForwardingMode,
+    pub velocity_secret: Vec<u8>,
+}
+
+/// Leaves the secret out of logs.
+impl fmt::Debug for ForwardingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForwardingConfig")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct BungeeCordForwarding {
+    pub hostname: String,
+    pub ip: IpAddr,
+    pub uuid: String,
+}
+
+/// Splits the `host\0ip\0uuid[\0properties]` server address written by BungeeCord with IP forwarding.
+/// Returns `None` when the address doesn't carry forwarding data.
+pub fn parse_bungeecord(
+    server_address: &str,
+) -> Result<Option<BungeeCordForwarding>, ConnectionError> {
+    let mut parts = server_address.split('\0');
+    let hostname = parts.next().unwrap_or_default();
+    let (ip, uuid) = match (parts.next(), parts.next()) {
+        (Some(ip), Some(uuid)) => (ip, uuid),
+        _ => return Ok(None),
+    };
+    if uuid.len() != 32 || !uuid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ConnectionError::Forwarding(
+            "Malformed BungeeCord forwarded UUID",
+        ));
+    }
+    Ok(Some(BungeeCordForwarding {
+        hostname: hostname.to_string(),
+        ip: IpAddr::from_str(ip)
+            .map_err(|_| ConnectionError::Forwarding("Malformed BungeeCord forwarded address"))?,
+        uuid: uuid.to_string(),
+    }))
+}
+
+/// Asks Velocity for the forwarded player info and verifies it against the shared secret.
+/// Must be called right after the whole Login Start packet has been read.
+pub async fn velocity_forwarded_ip<S: AsyncStream>(
+    socket: &mut S,
+    protocol: &Protocol,
+    addr: &SocketAddr,
+    secret: &[u8],
+) -> Result<IpAddr, ConnectionError> {
+    let request = protocol.encode(&LoginClientbound::LoginPluginRequest {
+        message_id: VELOCITY_MESSAGE_ID,
+        channel: VELOCITY_CHANNEL,
+        data: &[VELOCITY_MODERN_DEFAULT],
+    })?;
+    debug!("Writing Login Plugin Request to {}", &addr);
+    socket.write_all(&request).await?;
+
+    let response = match protocol.read(socket).await? {
+        LoginServerbound::LoginPluginResponse(response) => response,
+        packet => {
+            return Err(ConnectionError::UnexpectedPacket {
+                state: protocol.state(),
+                packet: packet.name(),
+            })
+        }
+    };
+    debug!("Read Login Plugin Response from {}", &addr);
+    if response.message_id != VELOCITY_MESSAGE_ID {
+        return Err(ConnectionError::Forwarding(
+            "Unexpected Login Plugin Response message id",
+        ));
+    }
+    let Some(data) = response.data else {
+        debug!("{} didn't understand velocity:player_info", &addr);
+        return Err(ConnectionError::Forwarding(
+            "Velocity modern forwarding is not enabled",
+        ));
+    };
+
+    let mut packet = data.as_slice();
+    let signature = get_bytes(&mut packet, VELOCITY_SIGNATURE_LENGTH)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+        .map_err(|_| ConnectionError::Forwarding("Invalid Velocity forwarding secret"))?;
+    mac.update(packet);
+    if mac.verify_slice(signature).is_err() {
+        debug!("{} sent player info with an invalid signature", &addr);
+        return Err(ConnectionError::Forwarding(
+            "Velocity forwarding signature mismatch",
+        ));
+    }
+
+    let version = get_varint(&mut packet)?;
+    if version < VELOCITY_MODERN_DEFAULT as i32 {
+        return Err(ConnectionError::Forwarding(
+            "Unsupported Velocity forwarding version",
+        ));
+    }
+    let ip = get_string(
+        &mut packet,
+        FORWARDED_ADDRESS_MAX_LENGTH,
+        "Velocity forwarded address",
+    )?;
+    // The rest is the profile, which isn't used.
+    IpAddr::from_str(&ip)
+        .map_err(|_| ConnectionError::Forwarding("Malformed Velocity forwarded address"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{frame, put_string, put_varint};
+    use crate::protocol::State;
+    use tokio::io::{duplex, BufReader};
+
+    static SECRET: &[u8] = b"secret";
+    static UUID: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    #[test]
+    fn bungeecord_addresses() {
+        let forwarded = parse_bungeecord(&format!("play.example.com\0198.51.100.7\0{}", UUID))
+            .unwrap()
+            .unwrap();
+        assert_eq!(forwarded.hostname, "play.example.com");
+        assert_eq!(forwarded.ip, IpAddr::from([198, 51, 100, 7]));
+        assert_eq!(forwarded.uuid, UUID);
+
+        let with_properties = format!("play.example.com\02001:db8::7\0{}\0[]", UUID);
+        let forwarded = parse_bungeecord(&with_properties).unwrap().unwrap();
+        assert_eq!(forwarded.ip, "2001:db8::7".parse::<IpAddr>().unwrap());
+
+        assert!(parse_bungeecord("play.example.com").unwrap().is_none());
+        assert!(parse_bungeecord("play.example.com\0198.51.100.7")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn malformed_bungeecord_addresses() {
+        for address in [
+            format!("play.example.com\0198.51.100.7\0{}", &UUID[1..]),
+            String::from("play.example.com\0198.51.100.7\0069a79f4-44e9-4726-a5be-fca90e38aaf5"),
+            format!("play.example.com\0198.51.100.7\0{}", UUID.replace('a', "g")),
+            format!("play.example.com\0198.51.100.300\0{}", UUID),
+            format!("play.example.com\0\0{}", UUID),
+        ] {
+            assert!(
+                matches!(
+                    parse_bungeecord(&address),
+                    Err(ConnectionError::Forwarding(_))
+                ),
+                "{:?}",
+                address
+            );
+        }
+    }
+
+    /// Velocity's answer to the player info request, signed with `secret`.
+    fn player_info(message_id: i32, version: i32, ip: &str, secret: &[u8]) -> Vec<u8> {
+        let mut info = Vec::new();
+        put_varint(&mut info, version);
+        put_string(&mut info, ip);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(&info);
+        let mut body = Vec::new();
+        put_varint(&mut body, message_id);
+        body.push(1);
+        body.extend_from_slice(&mac.finalize().into_bytes());
+        body.extend_from_slice(&info);
+        frame(0x02, &body)
+    }
+
+    async fn forward(response: &[u8]) -> Result<IpAddr, ConnectionError> {
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let (mut client, server) = duplex(64 * 1024);
+        client.write_all(response).await.unwrap();
+        let mut protocol = Protocol::new(ForwardingMode::Velocity);
+        protocol.transition(State::Login, 767).unwrap();
+        velocity_forwarded_ip(&mut BufReader::new(server), &protocol, &addr, SECRET).await
+    }
+
+    #[tokio::test]
+    async fn velocity_player_info() {
+        assert_eq!(
+            forward(&player_info(0, 1, "198.51.100.7", SECRET))
+                .await
+                .unwrap(),
+            IpAddr::from([198, 51, 100, 7])
+        );
+        // Newer versions only add to the end.
+        assert_eq!(
+            forward(&player_info(0, 4, "2001:db8::7", SECRET))
+                .await
+                .unwrap(),
+            "2001:db8::7".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_velocity_player_info() {
+        for (response, reason) in [
+            (
+                player_info(0, 1, "198.51.100.7", b"other"),
+                "Velocity forwarding signature mismatch",
+            ),
+            (
+                player_info(1, 1, "198.51.100.7", SECRET),
+                "Unexpected Login Plugin Response message id",
+            ),
+            (
+                player_info(0, 0, "198.51.100.7", SECRET),
+                "Unsupported Velocity forwarding version",
+            ),
+            (
+                player_info(0, 1, "198.51.100.300", SECRET),
+                "Malformed Velocity forwarded address",
+            ),
+            (
+                frame(0x02, &[0x00, 0x00]),
+                "Velocity modern forwarding is not enabled",
+            ),
+        ] {
+            match forward(&response).await {
+                Err(ConnectionError::Forwarding(actual)) => assert_eq!(actual, reason),
+                other => panic!("expected {:?}, got {:?}", reason, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::codec::{get_varint, read_varint};
+use crate::error::ConnectionError;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// A whole length prefixed packet, split into its ID and body.
+/// Whatever the client sent after it stays in the reader's buffer for the next frame.
+pub struct Frame {
+    pub id: i32,
+    packet: Vec<u8>,
+    body_start: usize,
+}
+
+impl Frame {
+    /// The fields after the packet ID. Fields the handler doesn't read are ignored.
+    pub fn body(&self) -> &[u8] {
+        &self.packet[self.body_start..]
+    }
+}
+
+/// Reads the next frame, refusing any longer than `max_length` bytes, packet ID included.
+/// `what` names the frame in errors.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_length: usize,
+    what: &'static str,
+) -> Result<Frame, ConnectionError> {
+    let length = usize::try_from(read_varint(reader).await?)
+        .map_err(|_| ConnectionError::Malformed("Negative packet length"))?;
+    if length > max_length {
+        return Err(ConnectionError::TooLong {
+            what,
+            length,
+            max: max_length,
+        });
+    }
+    let mut packet = vec![0u8; length];
+    reader.read_exact(&mut packet).await?;
+    let mut rest = packet.as_slice();
+    let id = get_varint(&mut rest)?;
+    let body_start = length - rest.len();
+    Ok(Frame {
+        id,
+        packet,
+        body_start,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn coalesced_frames() {
+        let mut reader: &[u8] = &[0x01, 0x00, 0x03, 0x01, 0xAA, 0xBB];
+        let frame = read_frame(&mut reader, 9, "Test").await.unwrap();
+        assert_eq!(frame.id, 0x00);
+        assert!(frame.body().is_empty());
+        let frame = read_frame(&mut reader, 9, "Test").await.unwrap();
+        assert_eq!(frame.id, 0x01);
+        assert_eq!(frame.body(), [0xAA, 0xBB]);
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn split_frames() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let reader = tokio::spawn(async move { read_frame(&mut server, 9, "Test").await });
+        for byte in [0x03u8, 0x01, 0xAA, 0xBB] {
+            client.write_all(&[byte]).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        let frame = reader.await.unwrap().unwrap();
+        assert_eq!(frame.id, 0x01);
+        assert_eq!(frame.body(), [0xAA, 0xBB]);
+    }
+
+    #[tokio::test]
+    async fn frame_limits() {
+        let mut reader: &[u8] = &[0x0A, 0x00];
+        assert!(matches!(
+            read_frame(&mut reader, 9, "Test").await,
+            Err(ConnectionError::TooLong {
+                length: 10,
+                max: 9,
+                ..
+            })
+        ));
+        let mut reader: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert!(matches!(
+            read_frame(&mut reader, 9, "Test").await,
+            Err(ConnectionError::Malformed(_))
+        ));
+        let mut reader: &[u8] = &[0x00];
+        assert!(matches!(
+            read_frame(&mut reader, 9, "Test").await,
+            Err(ConnectionError::Malformed(_))
+        ));
+        let mut reader: &[u8] = &[0x03, 0x00];
+        assert!(matches!(
+            read_frame(&mut reader, 9, "Test").await,
+            Err(ConnectionError::Disconnected)
+        ));
+    }
+}
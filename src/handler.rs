@@ -0,0 +1,55 @@
+use crate::message::{render_component, ConnectionInfo};
+use crate::protocol::Intent;
+use crate::vhost::Profile;
+use serde_json::Value;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Everything known about a connection by the time it gets a response.
+pub struct HandshakeContext<'a> {
+    /// The peer address, or the client address a proxy forwarded.
+    pub addr: &'a SocketAddr,
+    pub hostname: &'a str,
+    pub server_port: u16,
+    pub protocol_number: usize,
+    pub intent: Intent,
+    /// Only known once the client sent Login Start, so always `None` on status.
+    pub username: Option<&'a str>,
+    /// The profile of the virtual host the client connected to, with the access lists applied.
+    pub profile: &'a Profile,
+}
+
+/// Decides what a connection sees. Legacy pings carry no handshake and are answered from the profile,
+/// clients the [`VersionPolicy`](crate::version::VersionPolicy) doesn't support are only told which versions work.
+pub trait Handler: Send + Sync {
+    /// The JSON of the Status Response.
+    fn status(&self, context: &HandshakeContext<'_>) -> impl Future<Output = Value> + Send;
+
+    /// The text component of the Disconnect (Login) packet.
+    fn disconnect(&self, context: &HandshakeContext<'_>) -> impl Future<Output = Value> + Send;
+}
+
+/// Answers from the profile: its brand and status, and its disconnect message with the placeholders filled in.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultHandler;
+
+impl Handler for DefaultHandler {
+    async fn status(&self, context: &HandshakeContext<'_>) -> Value {
+        context
+            .profile
+            .status
+            .to_json(context.protocol_number, context.addr)
+    }
+
+    async fn disconnect(&self, context: &HandshakeContext<'_>) -> Value {
+        let info = ConnectionInfo {
+            addr: context.addr,
+            username: context.username.unwrap_or_default(),
+            protocol_number: context.protocol_number,
+            hostname: context.hostname,
+            server_port: context.server_port,
+            intent: context.intent.name(),
+        };
+        render_component(&context.profile.disconnect_message, &info)
+    }
+}
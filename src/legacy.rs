@@ -0,0 +1,170 @@
+use crate::error::ConnectionError;
+use crate::listener::AsyncStream;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tracing::debug;
+
+// https://minecraft.wiki/w/Java_Edition_protocol/Server_List_Ping#1.6
+pub static LEGACY_PING_PACKET_ID: u8 = 0xFE;
+static LEGACY_PING_PAYLOAD: u8 = 0x01;
+static LEGACY_PLUGIN_MESSAGE_ID: u8 = 0xFA;
+static LEGACY_KICK_PACKET_ID: u8 = 0xFF;
+static LEGACY_PING_CHANNEL: &str = "MC|PingHost";
+// 1.6 sends everything in one go, 1.4 and 1.5 stop after 0x01, Beta 1.8 to 1.3 stop after 0xFE.
+static LEGACY_PING_GRACE: Duration = Duration::from_millis(250);
+// Pre-Netty protocol numbers stop at 78 (1.6.4). Anything we can't tell is reported as incompatible.
+static LEGACY_UNKNOWN_PROTOCOL: u8 = 127;
+
+pub struct LegacyStatus<'a> {
+    pub brand: &'a str,
+    pub motd: &'a str,
+    pub online: usize,
+    pub max: usize,
+}
+
+/// What a legacy ping asked for. Only 1.6 says which protocol and hostname it wants.
+pub enum LegacyPing {
+    Beta,
+    Ping {
+        protocol_number: u8,
+        hostname: Option<String>,
+    },
+}
+
+impl LegacyPing {
+    pub fn hostname(&self) -> Option<&str> {
+        match self {
+            LegacyPing::Ping {
+                hostname: Some(hostname),
+                ..
+            } => Some(hostname),
+            _ => None,
+        }
+    }
+}
+
+pub async fn read_legacy_ping<S: AsyncStream>(
+    socket: &mut S,
+    addr: &SocketAddr,
+) -> Result<LegacyPing, ConnectionError> {
+    let byte = socket.read_u8().await?;
+    if byte != LEGACY_PING_PACKET_ID {
+        return Err(ConnectionError::UnknownPacketId {
+            packet: "legacy ping",
+            id: byte.into(),
+        });
+    }
+
+    match timeout(LEGACY_PING_GRACE, socket.read_u8()).await {
+        Ok(Ok(payload)) if payload == LEGACY_PING_PAYLOAD => {
+            match timeout(LEGACY_PING_GRACE, socket.read_u8()).await {
+                Ok(Ok(id)) if id == LEGACY_PLUGIN_MESSAGE_ID => {
+                    debug!("Read legacy MC|PingHost from {}", &addr);
+                    let (protocol_number, hostname) = read_ping_host(socket).await?;
+                    Ok(LegacyPing::Ping {
+                        protocol_number,
+                        hostname: Some(hostname),
+                    })
+                }
+                Ok(Ok(id)) => Err(ConnectionError::UnknownPacketId {
+                    packet: "legacy plugin message",
+                    id: id.into(),
+                }),
+                _ => {
+                    debug!("Read legacy 1.4 ping from {}", &addr);
+                    Ok(LegacyPing::Ping {
+                        protocol_number: LEGACY_UNKNOWN_PROTOCOL,
+                        hostname: None,
+                    })
+                }
+            }
+        }
+        Ok(Ok(_)) => Err(ConnectionError::Malformed("Unknown legacy ping payload")),
+        _ => {
+            debug!("Read legacy Beta 1.8 ping from {}", &addr);
+            Ok(LegacyPing::Beta)
+        }
+    }
+}
+
+pub async fn write_legacy_status<S: AsyncStream>(
+    socket: &mut S,
+    addr: &SocketAddr,
+    ping: &LegacyPing,
+    status: &LegacyStatus<'_>,
+) -> Result<(), ConnectionError> {
+    let response = match ping {
+        LegacyPing::Ping {
+            protocol_number, ..
+        } => format!(
+            "\u{a7}1\0{}\0{}\0{}\0{}\0{}",
+            protocol_number, status.brand, status.motd, status.online, status.max
+        ),
+        // The oldest format has no room for a version, and uses the section sign as delimiter.
+        LegacyPing::Beta => format!(
+            "{}\u{a7}{}\u{a7}{}",
+            status.motd.replace('\u{a7}', ""),
+            status.online,
+            status.max
+        ),
+    };
+
+    debug!("Writing legacy Kick packet to {}", &addr);
+    socket.write_all(&encode_kick(&response)?).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+async fn read_ping_host<S: AsyncStream>(socket: &mut S) -> Result<(u8, String), ConnectionError> {
+    let channel = read_legacy_string(socket).await?;
+    if channel != LEGACY_PING_CHANNEL {
+        return Err(ConnectionError::Malformed("Unknown legacy plugin channel"));
+    }
+    // Remaining length, then protocol number, hostname and port. The port isn't used.
+    let data_length = socket.read_u16().await? as usize;
+    if data_length < 7 {
+        return Err(ConnectionError::Malformed(
+            "Legacy plugin message too short",
+        ));
+    }
+    let protocol_number = socket.read_u8().await?;
+    let hostname = read_legacy_string(socket).await?;
+    socket.read_i32().await?;
+    Ok((protocol_number, hostname))
+}
+
+async fn read_legacy_string<S: AsyncStream>(socket: &mut S) -> Result<String, ConnectionError> {
+    let length = socket.read_u16().await? as usize;
+    if length > 255 {
+        return Err(ConnectionError::TooLong {
+            what: "Legacy string",
+            length,
+            max: 255,
+        });
+    }
+    let mut units = vec![0u16; length];
+    for unit in units.iter_mut() {
+        *unit = socket.read_u16().await?;
+    }
+    Ok(String::from_utf16(&units)?)
+}
+
+fn encode_kick(response: &str) -> Result<Vec<u8>, ConnectionError> {
+    let units: Vec<u16> = response.encode_utf16().collect();
+    if units.len() > u16::MAX as usize {
+        return Err(ConnectionError::TooLong {
+            what: "Legacy kick message",
+            length: units.len(),
+            max: u16::MAX as usize,
+        });
+    }
+    let mut packet = Vec::with_capacity(3 + units.len() * 2);
+    packet.push(LEGACY_KICK_PACKET_ID);
+    packet.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        packet.extend_from_slice(&unit.to_be_bytes());
+    }
+    Ok(packet)
+}
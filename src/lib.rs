@@ -0,0 +1,25 @@
+//! A Minecraft server that only answers the server list ping and turns every login away.
+//! The binary is a thin CLI over [`responder::Responder`], which answers a single connection.
+
+pub mod access;
+pub mod access_log;
+pub mod cidr;
+pub mod codec;
+pub mod config;
+pub mod error;
+pub mod forwarding;
+pub mod frame;
+pub mod handler;
+pub mod legacy;
+pub mod limit;
+pub mod listener;
+pub mod message;
+pub mod metrics;
+pub mod protocol;
+pub mod proxy;
+pub mod responder;
+pub mod shutdown;
+pub mod status;
+pub mod timeout;
+pub mod version;
+pub mod vhost;
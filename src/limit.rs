@@ -0,0 +1,235 @@
+use crate::cidr::mask;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Full buckets are only swept once the table grows past this size, and at most once per interval.
+static BUCKET_SWEEP_THRESHOLD: usize = 4096usize;
+static BUCKET_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// Connections over a limit that may be read and answered at once, the rest are closed right away.
+static REFUSAL_SLOTS: usize = 64usize;
+
+/// What happens to a connection over a limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    /// Close the socket without writing anything.
+    #[default]
+    Close,
+    /// Read the handshake, write a Disconnect (Login) packet if the client is logging in, then close.
+    /// Only a few connections are answered at once, the others are closed.
+    Disconnect,
+}
+
+/// Zero disables a limit.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pub rate_per_second: f64,
+    pub burst: f64,
+    pub per_ip_connections: usize,
+    pub global_connections: usize,
+    pub ipv6_prefix: u8,
+    pub action: LimitAction,
+    pub message: Value,
+}
+
+impl Limits {
+    /// IPv6 clients usually get a whole prefix, so they are limited by prefix rather than by address.
+    fn key(&self, ip: &IpAddr) -> IpAddr {
+        let ip = ip.to_canonical();
+        match ip {
+            IpAddr::V4(_) => ip,
+            IpAddr::V6(_) => mask(&ip, self.ipv6_prefix),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Rate,
+    PerIp,
+    Global,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Rate => write!(f, "rate limited"),
+            LimitExceeded::PerIp => write!(f, "too many connections from this address"),
+            LimitExceeded::Global => write!(f, "too many connections"),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<IpAddr, Bucket>,
+    swept: Option<Instant>,
+}
+
+/// Connection limits shared by every listener. Survives config reloads, the limits themselves don't.
+#[derive(Default)]
+pub struct Limiter {
+    buckets: Mutex<Buckets>,
+    active: Mutex<HashMap<IpAddr, usize>>,
+    global_active: AtomicUsize,
+    refusing: AtomicUsize,
+    pub closed: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+impl Limiter {
+    /// Takes a slot out of the global cap. Call it before anything else is done with the socket.
+    pub fn acquire_global(self: &Arc<Self>, limits: &Limits) -> Result<GlobalSlot, LimitExceeded> {
+        let active = self.global_active.fetch_add(1, Ordering::AcqRel);
+        let slot = GlobalSlot {
+            limiter: self.clone(),
+        };
+        if limits.global_connections != 0 && active >= limits.global_connections {
+            return Err(LimitExceeded::Global);
+        }
+        Ok(slot)
+    }
+
+    /// Takes a token from the rate limit bucket and a slot out of the per IP cap.
+    pub fn acquire(
+        self: &Arc<Self>,
+        ip: &IpAddr,
+        limits: &Limits,
+    ) -> Result<IpSlot, LimitExceeded> {
+        let key = limits.key(ip);
+        if limits.rate_per_second > 0.0 && !self.take_token(key, limits) {
+            return Err(LimitExceeded::Rate);
+        }
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(key).or_insert(0);
+        if limits.per_ip_connections != 0 && *count >= limits.per_ip_connections {
+            return Err(LimitExceeded::PerIp);
+        }
+        *count += 1;
+        Ok(IpSlot {
+            limiter: self.clone(),
+            key,
+        })
+    }
+
+    /// Takes one of the few slots for telling a connection over a limit why it's turned away.
+    /// Returns `None` when they're all taken, the connection should then be closed without a read.
+    pub fn acquire_refusal(self: &Arc<Self>) -> Option<RefusalSlot> {
+        let refusing = self.refusing.fetch_add(1, Ordering::AcqRel);
+        let slot = RefusalSlot {
+            limiter: self.clone(),
+        };
+        if refusing >= REFUSAL_SLOTS {
+            return None;
+        }
+        Some(slot)
+    }
+
+    /// Counts a rejected connection under the outcome it got.
+    pub fn record(&self, action: LimitAction) {
+        let counter = match action {
+            LimitAction::Close => &self.closed,
+            LimitAction::Disconnect => &self.disconnected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn take_token(&self, key: IpAddr, limits: &Limits) -> bool {
+        let now = Instant::now();
+        let burst = limits.burst.max(1.0);
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.by_key.len() >= BUCKET_SWEEP_THRESHOLD
+            && buckets
+                .swept
+                .is_none_or(|swept| now.duration_since(swept) >= BUCKET_SWEEP_INTERVAL)
+        {
+            buckets.by_key.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * limits.rate_per_second < burst
+            });
+            buckets.swept = Some(now);
+        }
+        let bucket = buckets.by_key.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limits.rate_per_second).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Holds a slot of the global cap until dropped.
+pub struct GlobalSlot {
+    limiter: Arc<Limiter>,
+}
+
+impl Drop for GlobalSlot {
+    fn drop(&mut self) {
+        self.limiter.global_active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Holds a refusal slot until dropped.
+pub struct RefusalSlot {
+    limiter: Arc<Limiter>,
+}
+
+impl Drop for RefusalSlot {
+    fn drop(&mut self) {
+        self.limiter.refusing.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Holds a slot of the per IP cap until dropped.
+pub struct IpSlot {
+    limiter: Arc<Limiter>,
+    key: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refusal_slots() {
+        let limiter = Arc::new(Limiter::default());
+        let slots: Vec<_> = (0..REFUSAL_SLOTS)
+            .map(|_| limiter.acquire_refusal().unwrap())
+            .collect();
+        assert!(limiter.acquire_refusal().is_none());
+        drop(slots);
+        assert!(limiter.acquire_refusal().is_some());
+        assert_eq!(limiter.refusing.load(Ordering::Acquire), 0);
+    }
+}
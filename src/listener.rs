@@ -0,0 +1,241 @@
+use crate::responder::Responder;
+use std::error::Error;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+pub static DEFAULT_PLACEHOLDER_ADDRESS: &str = "127.0.0.1:0";
+static UNIX_ADDRESS_PREFIX: &str = "unix:";
+static LISTEN_BACKLOG: i32 = 1024i32;
+// Accept errors are mostly running out of file descriptors, retrying right away would spin.
+pub static ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Every connection is read through a buffer, so the first byte can be looked at without consuming it.
+pub type Socket = BufReader<Stream>;
+
+/// What the Minecraft protocol is spoken over, once the PROXY header is out of the way.
+/// Any `AsyncRead + AsyncWrite` transport fits once wrapped in a `BufReader`.
+pub trait AsyncStream: AsyncBufRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncBufRead + AsyncWrite + Unpin> AsyncStream for T {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    /// Written as `unix:/path/to/socket`.
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    /// Host names are resolved once, the first address they resolve to is bound.
+    pub fn parse(address: &str) -> Result<Self, Box<dyn Error>> {
+        if let Some(path) = address.strip_prefix(UNIX_ADDRESS_PREFIX) {
+            if path.is_empty() {
+                return Err(Box::from("Unix socket path is empty"));
+            }
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+        match address.to_socket_addrs()?.next() {
+            Some(addr) => Ok(ListenAddress::Tcp(addr)),
+            None => Err(Box::from(format!(
+                "{} didn't resolve to any address",
+                address
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddress::Unix(path) => write!(f, "{}{}", UNIX_ADDRESS_PREFIX, path.display()),
+        }
+    }
+}
+
+/// A validated `[[listener]]`, with the listener's overrides already applied to its responder's virtual hosts.
+#[derive(Clone, Debug)]
+pub struct ListenerConfig {
+    pub address: ListenAddress,
+    /// Whether an IPv6 listener also accepts IPv4 clients, as IPv4-mapped addresses.
+    pub dual_stack: bool,
+    /// Peer address of Unix socket connections, until a PROXY header says otherwise.
+    pub placeholder: SocketAddr,
+    /// Whether every connection must open with a PROXY header.
+    pub proxy_protocol: bool,
+    pub responder: Responder,
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf, SocketAddr),
+}
+
+impl Listener {
+    pub fn bind(config: &ListenerConfig) -> Result<Self, Box<dyn Error>> {
+        match &config.address {
+            ListenAddress::Tcp(addr) => {
+                let socket = socket2::Socket::new(
+                    socket2::Domain::for_address(*addr),
+                    socket2::Type::STREAM,
+                    None,
+                )?;
+                if addr.is_ipv6() {
+                    socket.set_only_v6(!config.dual_stack)?;
+                }
+                #[cfg(unix)]
+                socket.set_reuse_address(true)?;
+                socket.set_nonblocking(true)?;
+                socket.bind(&(*addr).into())?;
+                socket.listen(LISTEN_BACKLOG)?;
+                Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // A socket left behind by a previous run would fail the bind.
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                Ok(Listener::Unix(
+                    UnixListener::bind(path)?,
+                    path.clone(),
+                    config.placeholder,
+                ))
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => {
+                Err(Box::from("Unix sockets are not supported on this platform"))
+            }
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, SocketAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                // Dual-stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses.
+                let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+                Ok((Stream::new(Transport::Tcp(stream)), peer))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _, placeholder) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Stream::new(Transport::Unix(stream)), *placeholder))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path, _) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// An accepted connection, counting the bytes that go through it.
+pub struct Stream {
+    transport: Transport,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl Stream {
+    fn new(transport: Transport) -> Self {
+        Stream {
+            transport,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    /// Unix socket peers are local, so they are always trusted to send a PROXY header.
+    pub fn is_local(&self) -> bool {
+        match self.transport {
+            Transport::Tcp(_) => false,
+            #[cfg(unix)]
+            Transport::Unix(_) => true,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = match &mut this.transport {
+            Transport::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        };
+        this.bytes_read += (buf.filled().len() - filled) as u64;
+        result
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = match &mut this.transport {
+            Transport::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        };
+        if let Poll::Ready(Ok(written)) = result {
+            this.bytes_written += written as u64;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().transport {
+            Transport::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().transport {
+            Transport::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -1,19 +1,40 @@
 use clap::ArgAction;
 use clap::Parser;
-use flashlight::create_varint;
-use rolling_glass::{is_known_protocol_number, ProtocolNum};
-use serde_json::json;
+use rolling_looking_glass::access::AccessAction;
+use rolling_looking_glass::access_log::{AccessLog, AccessRecord};
+use rolling_looking_glass::config::{
+    watch_config, AccessLogSection, AccessSection, Config, ConfigFile, ConfigSource,
+    ForwardingSection, LimitsSection, ListenerSection, MessagesSection, MetricsSection,
+    ProxyProtocolSection, StatusSection, TimeoutsSection, VersionsSection, DEFAULT_ADDRESS,
+    DEFAULT_BRAND,
+};
+use rolling_looking_glass::error::ConnectionError;
+use rolling_looking_glass::forwarding::ForwardingMode;
+use rolling_looking_glass::limit::{LimitAction, LimitExceeded, Limiter};
+use rolling_looking_glass::listener::{
+    ListenAddress, Listener, Socket, ACCEPT_ERROR_BACKOFF, DEFAULT_PLACEHOLDER_ADDRESS,
+};
+use rolling_looking_glass::message::DEFAULT_DISCONNECT_MESSAGE;
+use rolling_looking_glass::metrics::{serve_metrics, Metrics};
+use rolling_looking_glass::proxy::accept_proxy_header;
+use rolling_looking_glass::shutdown::shutdown_signal;
+use rolling_looking_glass::status::ShowIp;
+use rolling_looking_glass::timeout::{within, PenaltyBox};
 use std::error::Error;
 use std::net::SocketAddr;
-use std::slice;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 use time::macros::format_description;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::level_filters::LevelFilter;
+use tokio::io::BufReader;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
 use tracing::{debug, info, warn};
 use tracing_subscriber::fmt::time::OffsetTime;
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*, reload};
 
 #[derive(Parser)]
 #[command(long_about = None, disable_help_flag = true)]
@@ -24,224 +45,574 @@ struct RollingLookingGlassArguments {
     #[arg(
         short,
         long = "address",
-        help = "Listening address",
-        default_value = "127.0.0.1:25565"
+        help = "Listening address, or unix:/path/to/socket. Repeat it to listen on several",
+        default_value = DEFAULT_ADDRESS
     )]
-    address: String,
+    address: Vec<String>,
 
-    #[arg(short, long = "brand", default_value = "Void")]
+    #[arg(
+        long = "unix-placeholder-address",
+        help = "Peer address of Unix socket connections. With --proxy-protocol, only LOCAL and UNKNOWN headers keep it",
+        default_value = DEFAULT_PLACEHOLDER_ADDRESS
+    )]
+    unix_placeholder_address: String,
+
+    #[arg(short, long = "brand", default_value = DEFAULT_BRAND)]
     brand: String,
+
+    #[arg(
+        short,
+        long = "motd",
+        help = "MOTD as plain text or a JSON text component",
+        default_value = ""
+    )]
+    motd: String,
+
+    #[arg(long = "max-players", default_value_t = 0)]
+    max_players: usize,
+
+    #[arg(long = "online-players", default_value_t = 0)]
+    online_players: usize,
+
+    #[arg(
+        long = "sample-player",
+        help = "Player sample entry as NAME or NAME=UUID, can be repeated"
+    )]
+    sample_players: Vec<String>,
+
+    #[arg(short, long = "favicon", help = "Path to a 64x64 PNG favicon")]
+    favicon: Option<PathBuf>,
+
+    #[arg(long = "enforces-secure-chat", default_value_t = false)]
+    enforces_secure_chat: bool,
+
+    #[arg(
+        long = "show-ip",
+        help = "Show the visitor's IP address in the server list",
+        value_enum,
+        default_value_t = ShowIp::None
+    )]
+    show_ip: ShowIp,
+
+    #[arg(
+        short,
+        long = "disconnect-message",
+        help = "Disconnect (Login) message as plain text or a JSON text component. Placeholders: {ip}, {port}, {username}, {protocol}, {version_name}, {hostname}, {server_port}, {intent}",
+        default_value = DEFAULT_DISCONNECT_MESSAGE
+    )]
+    disconnect_message: String,
+
+    #[arg(
+        long = "proxy-protocol",
+        help = "Require a PROXY protocol v1 or v2 header on every connection",
+        default_value_t = false
+    )]
+    proxy_protocol: bool,
+
+    #[arg(
+        long = "proxy-trusted",
+        help = "IP or CIDR range allowed to send PROXY headers, can be repeated. Required with --proxy-protocol on TCP addresses"
+    )]
+    proxy_trusted: Vec<String>,
+
+    #[arg(
+        long = "forwarding",
+        help = "Client address forwarding used by a proxy in front of this service",
+        value_enum,
+        default_value_t = ForwardingMode::None
+    )]
+    forwarding: ForwardingMode,
+
+    #[arg(
+        long = "velocity-secret-file",
+        help = "Path to the Velocity modern forwarding secret"
+    )]
+    velocity_secret_file: Option<PathBuf>,
+
+    #[arg(
+        long = "min-protocol",
+        help = "Lowest protocol number answered, older clients are told which versions work"
+    )]
+    min_protocol: Option<i32>,
+
+    #[arg(
+        long = "max-protocol",
+        help = "Highest protocol number answered, newer clients are told which versions work"
+    )]
+    max_protocol: Option<i32>,
+
+    #[arg(
+        long = "accept-unknown-protocols",
+        help = "Answer protocol numbers this build doesn't know, such as snapshots",
+        default_value_t = false
+    )]
+    accept_unknown_protocols: bool,
+
+    #[arg(
+        long = "versions-name",
+        help = "How the supported versions are named to unsupported clients. Defaults to the releases at either end of the window"
+    )]
+    versions_name: Option<String>,
+
+    #[arg(
+        long = "virtual-hosts",
+        help = "Path to a TOML file of per-hostname brands, status responses and messages"
+    )]
+    virtual_hosts: Option<PathBuf>,
+
+    #[arg(
+        long = "allowlist",
+        help = "Path to a file of IPs and CIDR ranges allowed to connect, reloaded when it changes"
+    )]
+    allowlist: Option<PathBuf>,
+
+    #[arg(
+        long = "denylist",
+        help = "Path to a file of IPs and CIDR ranges denied, with an optional action and message each, reloaded when it changes"
+    )]
+    denylist: Option<PathBuf>,
+
+    #[arg(
+        long = "handshake-timeout-ms",
+        help = "Deadline for the handshake and Login Start",
+        default_value_t = TimeoutsSection::default().handshake_ms
+    )]
+    handshake_timeout_ms: u64,
+
+    #[arg(
+        long = "status-timeout-ms",
+        help = "Deadline for the status and ping exchange",
+        default_value_t = TimeoutsSection::default().status_ms
+    )]
+    status_timeout_ms: u64,
+
+    #[arg(
+        long = "connection-timeout-ms",
+        help = "Deadline for the whole connection",
+        default_value_t = TimeoutsSection::default().connection_ms
+    )]
+    connection_timeout_ms: u64,
+
+    #[arg(
+        long = "write-timeout-ms",
+        help = "Deadline for every write",
+        default_value_t = TimeoutsSection::default().write_ms
+    )]
+    write_timeout_ms: u64,
+
+    #[arg(
+        long = "timeout-penalty-ms",
+        help = "How long peers that timed out are refused for, 0 disables",
+        default_value_t = TimeoutsSection::default().penalty_ms
+    )]
+    timeout_penalty_ms: u64,
+
+    #[arg(
+        long = "shutdown-grace-ms",
+        help = "How long active connections get to finish after SIGTERM or SIGINT",
+        default_value_t = TimeoutsSection::default().shutdown_grace_ms
+    )]
+    shutdown_grace_ms: u64,
+
+    #[arg(
+        long = "rate-limit",
+        help = "New connections per second and per source, 0 disables",
+        default_value_t = LimitsSection::default().rate_per_second
+    )]
+    rate_limit: f64,
+
+    #[arg(
+        long = "rate-limit-burst",
+        default_value_t = LimitsSection::default().burst
+    )]
+    rate_limit_burst: u32,
+
+    #[arg(
+        long = "max-connections-per-ip",
+        help = "Concurrent connections per source, 0 disables",
+        default_value_t = LimitsSection::default().per_ip_connections
+    )]
+    max_connections_per_ip: usize,
+
+    #[arg(
+        long = "max-connections",
+        help = "Concurrent connections in total, 0 disables",
+        default_value_t = LimitsSection::default().global_connections
+    )]
+    max_connections: usize,
+
+    #[arg(
+        long = "ipv6-prefix",
+        help = "Prefix length IPv6 sources are limited by",
+        default_value_t = LimitsSection::default().ipv6_prefix
+    )]
+    ipv6_prefix: u8,
+
+    #[arg(
+        long = "limit-action",
+        help = "What connections over a limit get",
+        value_enum,
+        default_value_t = LimitAction::Close
+    )]
+    limit_action: LimitAction,
+
+    #[arg(
+        long = "limit-message",
+        help = "Disconnect (Login) message for connections over a limit",
+        default_value_t = LimitsSection::default().message
+    )]
+    limit_message: String,
+
+    #[arg(
+        long = "metrics-address",
+        help = "Serve Prometheus metrics on http://METRICS_ADDRESS/metrics"
+    )]
+    metrics_address: Option<String>,
+
+    #[arg(
+        long = "access-log",
+        help = "Path to a file that gets one JSON line per connection"
+    )]
+    access_log: Option<PathBuf>,
+
+    #[arg(
+        long = "access-log-max-bytes",
+        help = "Rotate the access log once it would grow past this size, 0 disables",
+        default_value_t = AccessLogSection::default().max_bytes
+    )]
+    access_log_max_bytes: u64,
+
+    #[arg(
+        long = "access-log-max-age-secs",
+        help = "Rotate the access log once it is this old, 0 disables",
+        default_value_t = AccessLogSection::default().max_age_secs
+    )]
+    access_log_max_age_secs: u64,
+
+    #[arg(
+        long = "access-log-keep",
+        help = "How many rotated access logs to keep",
+        default_value_t = AccessLogSection::default().keep
+    )]
+    access_log_keep: usize,
+
+    #[arg(
+        short,
+        long = "config",
+        help = "Path to a TOML config file. Replaces every other option, and is reloaded on SIGHUP or when it changes"
+    )]
+    config: Option<PathBuf>,
+}
+
+impl RollingLookingGlassArguments {
+    fn config_source(self) -> ConfigSource {
+        if let Some(path) = self.config {
+            return ConfigSource::File(path);
+        }
+        let placeholder_address = self.unix_placeholder_address;
+        ConfigSource::Arguments(Box::new(ConfigFile {
+            listener: self
+                .address
+                .into_iter()
+                .map(|address| ListenerSection {
+                    placeholder_address: Some(placeholder_address.clone()),
+                    ..ListenerSection::new(address)
+                })
+                .collect(),
+            status: StatusSection {
+                brand: self.brand,
+                motd: self.motd,
+                max_players: self.max_players,
+                online_players: self.online_players,
+                sample_players: self.sample_players,
+                favicon: self.favicon,
+                enforces_secure_chat: self.enforces_secure_chat,
+                show_ip: self.show_ip,
+            },
+            messages: MessagesSection {
+                disconnect: self.disconnect_message,
+            },
+            proxy_protocol: ProxyProtocolSection {
+                enabled: self.proxy_protocol,
+                trusted: self.proxy_trusted,
+            },
+            forwarding: ForwardingSection {
+                mode: self.forwarding,
+                velocity_secret_file: self.velocity_secret_file,
+            },
+            versions: VersionsSection {
+                min: self.min_protocol,
+                max: self.max_protocol,
+                accept_unknown: self.accept_unknown_protocols,
+                name: self.versions_name,
+            },
+            virtual_hosts_file: self.virtual_hosts,
+            timeouts: TimeoutsSection {
+                handshake_ms: self.handshake_timeout_ms,
+                status_ms: self.status_timeout_ms,
+                connection_ms: self.connection_timeout_ms,
+                write_ms: self.write_timeout_ms,
+                penalty_ms: self.timeout_penalty_ms,
+                shutdown_grace_ms: self.shutdown_grace_ms,
+            },
+            limits: LimitsSection {
+                rate_per_second: self.rate_limit,
+                burst: self.rate_limit_burst,
+                per_ip_connections: self.max_connections_per_ip,
+                global_connections: self.max_connections,
+                ipv6_prefix: self.ipv6_prefix,
+                action: self.limit_action,
+                message: self.limit_message,
+            },
+            access: AccessSection {
+                allowlist: self.allowlist,
+                denylist: self.denylist,
+                ..AccessSection::default()
+            },
+            metrics: MetricsSection {
+                address: self.metrics_address,
+            },
+            access_log: AccessLogSection {
+                path: self.access_log,
+                max_bytes: self.access_log_max_bytes,
+                max_age_secs: self.access_log_max_age_secs,
+                keep: self.access_log_keep,
+            },
+            ..ConfigFile::default()
+        }))
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let args = RollingLookingGlassArguments::parse();
+    let source = RollingLookingGlassArguments::parse().config_source();
+    let config = source.load()?;
+    let (log_filter, log_filter_handle) = reload::Layer::new(config.log_filter());
     tracing_subscriber::registry()
+        .with(log_filter)
         .with(fmt::layer().with_timer(OffsetTime::new(time::UtcOffset::current_local_offset()?, format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3][offset_hour sign:mandatory]:[offset_minute]"))).with_target(false))
-        .with(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
         .init();
 
     debug!("Debug logging enabled");
-    let listener = TcpListener::bind(&args.address).await?;
-    info!("Listening on {}", &args.address);
-    info!("Brand name: {}", &args.brand);
-
-    let brand: &'static str = Box::leak(args.brand.into_boxed_str());
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        tokio::spawn(async move {
-            info!("New connection from {}", &addr);
-            if let Err(e) = handle_packets(socket, &addr, brand).await {
-                warn!("{} error: {}", &addr, e);
+    let (accept_sender, mut accept_receiver) = mpsc::channel(64);
+    let mut listeners = JoinSet::new();
+    for listener_config in &config.listeners {
+        let listener = Listener::bind(listener_config)?;
+        info!("Listening on {}", &listener_config.address);
+        let address = listener_config.address.clone();
+        let accept_sender = accept_sender.clone();
+        listeners.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok(accepted) => {
+                        if accept_sender
+                            .send((address.clone(), accepted))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Unable to accept on {}: {}", &address, e);
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    }
+                }
             }
-            info!("Connection from {} is closed", &addr);
         });
     }
-}
-
-// https://minecraft.wiki/w/Java_Edition_protocol/Packets#Handshake
-static HANDSHAKE_MAX_LENGTH: usize = 263usize;
-static PING_REQUEST_LENGTH: usize = 9usize;
-
-async fn handle_packets(
-    mut socket: TcpStream,
-    addr: &SocketAddr,
-    brand: &str,
-) -> Result<(), Box<dyn Error>> {
-    let resize = read_varint(&mut socket).await?;
-    let handshake_length = resize;
-    if handshake_length > HANDSHAKE_MAX_LENGTH {
-        debug!("{} sent a handshake packet that's too large", &addr);
-        return Err(Box::from("Handshake packet too large"));
-    }
-
-    let mut byte: u8 = 255u8;
-
-    socket.read_exact(slice::from_mut(&mut byte)).await?;
-    if byte != 0u8 {
-        debug!(
-            "{} sent a handshake packet that has incorrect packet id",
-            &addr
-        );
-        return Err(Box::from("Unknown packet id"));
-    }
-
-    let rev = read_varint(&mut socket).await?;
-    let protocol_number = rev;
-    if !is_known_protocol_number(protocol_number as ProtocolNum) {
-        debug!(
-            "{} sent a handshake packet that has unknown protocol number",
-            &addr
-        );
-        return Err(Box::from("Unknown protocol number"));
-    }
-    debug!("Read protocol number {} from {}", protocol_number, &addr);
-
-    // Read server address. It's not used.
-    let resize = read_varint(&mut socket).await?;
-    if resize > 255 {
-        debug!("{} sent a server address that's too long", &addr);
-        return Err(Box::from("Server address too long"));
-    }
-    socket.read_exact(&mut vec![0u8; resize]).await?;
-    debug!("Read server address from {}", &addr);
-
-    // Read server port number. It's not used.
-    socket.read_u16().await?;
-    debug!("Read server port number from {}", &addr);
-
-    // Read intent number. It must be either 1 or 2.
-    socket.read_exact(slice::from_mut(&mut byte)).await?;
-    if byte != 1u8 && byte != 2u8 {
-        return Err(Box::from(
-            "The intent number must be either 1 (Status) or 2 (Login).",
-        ));
-    }
-    debug!("Read intent number {} from {}", byte, &addr);
-
-    if byte == 1u8 {
-        let resize = read_varint(&mut socket).await?;
-        if resize != 1 {
-            return Err(Box::from("Not a Status Request"));
-        }
-        // Status Request
-        socket.read_exact(slice::from_mut(&mut byte)).await?;
-        if byte != 0u8 {
-            debug!(
-                "{} sent a Status Request packet that has incorrect packet id",
-                &addr
-            );
-            return Err(Box::from("Unknown packet id"));
-        }
-        debug!("Read Status Request from {}", &addr);
-
-        // Status Response
-        let payload = json!({
-            "version": json!({
-                "name": brand,
-                "protocol": protocol_number
-            }),
-            "players": json!({
-                "max": 0,
-                "online": 0,
-                "sample": []
-            })
-        })
-        .to_string();
-        let strlen = payload.len();
-        let strlen_varint = create_varint(strlen as i32);
-        let packet_len = 1 + strlen_varint.len() + strlen;
-        let packet_len_varint = create_varint(packet_len as i32);
-        debug!("Writing Status Response to {}", &addr);
-        socket.write_all(&packet_len_varint).await?;
-        socket.write_u8(0x00).await?;
-        socket.write_all(&strlen_varint).await?;
-        socket.write_all(payload.as_bytes()).await?;
-
-        debug!("Waiting for Ping Request from {}", &addr);
-        // Ping Request
-        let resize = read_varint(&mut socket).await?;
-        let ping_request_length = resize;
-        if ping_request_length == PING_REQUEST_LENGTH {
-            socket.read_exact(slice::from_mut(&mut byte)).await?;
-            if byte != 1u8 {
-                debug!(
-                    "{} sent a Ping Request packet that has incorrect packet id",
-                    &addr
-                );
-                return Err(Box::from("Unknown packet id"));
-            }
-
-            debug!("Writing Ping Response to {}", &addr);
-            let ping_long = socket.read_i64().await?;
-            // Pong Response
-            socket.write_u8(PING_REQUEST_LENGTH as u8).await?;
-            socket.write_all(slice::from_mut(&mut byte)).await?;
-            socket.write_i64(ping_long).await?;
-        } else {
-            return Err(Box::from("Not a Ping Request"));
-        }
-    } else {
-        read_varint(&mut socket).await?;
-
-        socket.read_exact(slice::from_mut(&mut byte)).await?;
-        if byte != 0u8 {
-            debug!(
-                "{} sent a Login Start packet that has incorrect packet id",
-                &addr
-            );
-            return Err(Box::from("Unknown packet id"));
+    drop(accept_sender);
+    let metrics_listener = match &config.metrics_address {
+        Some(address) => {
+            let listener = TcpListener::bind(address).await?;
+            info!("Serving metrics on http://{}/metrics", address);
+            Some(listener)
         }
+        None => None,
+    };
+    config.log_summary();
 
-        let resize = read_varint(&mut socket).await?;
-        let ign_length = resize;
-        if ign_length == 0 || ign_length > 16 {
-            debug!("{} sent an illegal username which is too long", &addr);
-            return Err(Box::from("Username too long"));
-        }
-        // Immediately send Disconnect (Login), the rest of the buffer is ignored.
-        let payload = json!({
-            "text": format!("Your IP address is {}", &addr.ip()),
-        })
-        .to_string();
-        let strlen = payload.len();
-        let strlen_varint = create_varint(strlen as i32);
-        let packet_len = 1 + strlen_varint.len() + strlen;
-        let packet_len_varint = create_varint(packet_len as i32);
-        debug!("Writing Disconnect (Login) packet to {}", &addr);
-        socket.write_all(&packet_len_varint).await?;
-        socket.write_u8(0x00).await?;
-        socket.write_all(&strlen_varint).await?;
-        socket.write_all(payload.as_bytes()).await?;
-        socket.shutdown().await?;
+    let (config_sender, config_receiver) = watch::channel(Arc::new(config));
+    tokio::spawn(watch_config(source, config_sender, log_filter_handle));
+    let penalties = Arc::new(PenaltyBox::default());
+    let limiter = Arc::new(Limiter::default());
+    let metrics = Arc::new(Metrics::default());
+    let access_log = match &config_receiver.borrow().access_log {
+        Some(access_log) => Some(Arc::new(AccessLog::open(access_log)?)),
+        None => None,
+    };
+    if let Some(listener) = metrics_listener {
+        tokio::spawn(serve_metrics(listener, metrics.clone(), limiter.clone()));
     }
+    let mut connections = JoinSet::new();
+    let mut accepted = 0u64;
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    let signal = loop {
+        let (address, (stream, peer)) = tokio::select! {
+            signal = &mut signal => break signal,
+            // Finished connections are reaped as they go, so the set only holds active ones.
+            Some(_) = connections.join_next() => continue,
+            Some(accepted) = accept_receiver.recv() => accepted,
+        };
+        let mut socket = BufReader::new(stream);
+        accepted += 1;
+        let config = config_receiver.borrow().clone();
+        let penalties = penalties.clone();
+        let limiter = limiter.clone();
+        let metrics = metrics.clone();
+        let access_log = access_log.clone();
+        let id = accepted;
+        connections.spawn(async move {
+            let _active = metrics.track();
+            let started = Instant::now();
+            let mut record = AccessRecord::new(id, address.to_string(), &peer);
+            'serve: {
+                let _global_slot = match limiter.acquire_global(&config.limits) {
+                    Ok(slot) => slot,
+                    Err(reason) => {
+                        metrics.rejection(&reason.to_string());
+                        record.reject(reason.to_string());
+                        reject(&mut socket, &peer, reason, &config, &address, &limiter).await;
+                        break 'serve;
+                    }
+                };
+                let timeouts = &config.timeouts;
+                let addr = if config.listener(&address).proxy_protocol {
+                    match within(
+                        timeouts.handshake,
+                        "PROXY header",
+                        accept_proxy_header(&mut socket, &peer, &config.proxy),
+                    )
+                    .await
+                    {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            if e.is_benign() {
+                                debug!("{} {}", &peer, e);
+                            } else {
+                                warn!("{} error: {}", &peer, e);
+                            }
+                            metrics.rejection(&e.reason());
+                            record.fail(&e);
+                            break 'serve;
+                        }
+                    }
+                } else {
+                    peer
+                };
+                record.set_peer(&addr);
+                if penalties.is_penalized(&addr.ip()) {
+                    debug!("Refused {} which recently timed out", &addr);
+                    metrics.rejection("Recently timed out");
+                    record.reject(String::from("Recently timed out"));
+                    break 'serve;
+                }
+                let access = config.access.check(&addr.ip());
+                if access == Some(&AccessAction::Drop) {
+                    debug!("Dropped {} by the access lists", &addr);
+                    metrics.rejection("Denied by the access lists");
+                    record.reject(String::from("Denied by the access lists"));
+                    break 'serve;
+                }
+                let _ip_slot = match limiter.acquire(&addr.ip(), &config.limits) {
+                    Ok(slot) => slot,
+                    Err(reason) => {
+                        metrics.rejection(&reason.to_string());
+                        record.reject(reason.to_string());
+                        reject(&mut socket, &addr, reason, &config, &address, &limiter).await;
+                        break 'serve;
+                    }
+                };
+                info!("New connection from {}", &addr);
+                let result = within(
+                    timeouts.connection,
+                    "Connection",
+                    config.listener(&address).responder.respond_with(
+                        &mut socket,
+                        &addr,
+                        access,
+                        &metrics,
+                        &mut record,
+                    ),
+                )
+                .await;
+                if let Err(e) = result {
+                    metrics.rejection(&e.reason());
+                    record.fail(&e);
+                    match e {
+                        ConnectionError::TimedOut(_) => {
+                            info!("{} {}", &addr, e);
+                            penalties.penalize(addr.ip(), timeouts.penalty);
+                        }
+                        _ if e.is_benign() => debug!("{} {}", &addr, e),
+                        _ => warn!("{} error: {}", &addr, e),
+                    }
+                }
+                info!("Connection from {} is closed", &addr);
+            }
+            if let Some(access_log) = &access_log {
+                record.finish(socket.get_ref(), started);
+                access_log.write(&record);
+            }
+        });
+    };
 
+    listeners.shutdown().await;
+    let grace = config_receiver.borrow().timeouts.shutdown_grace;
+    let active = connections.len();
+    info!(
+        "Received {}, no longer accepting connections, waiting up to {} ms for {} active ones",
+        signal,
+        grace.as_millis(),
+        active
+    );
+    let _ = tokio::time::timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    let aborted = connections.len();
+    connections.shutdown().await;
+    info!(
+        "Shut down after {} connections, {} of the {} active ones finished and {} were aborted",
+        accepted,
+        active - aborted,
+        active,
+        aborted
+    );
     Ok(())
 }
 
-async fn read_varint(stream: &mut TcpStream) -> Result<usize, Box<dyn Error>> {
-    let mut byte = 0x00;
-    let mut res = 0i32;
-    for i in 0.. {
-        if i > 5 {
-            return Err(Box::from("Not a valid varint"));
-        }
-        let buf = slice::from_mut(&mut byte);
-        stream.read_exact(buf).await?;
-        if buf.is_empty() {
-            break;
-        }
-        res |= ((buf[0] as i32) & 0x7Fi32) << (7 * i);
-        if ((buf[0] as i32) & 0x80i32) == 0 {
-            break;
+async fn reject(
+    socket: &mut Socket,
+    addr: &SocketAddr,
+    reason: LimitExceeded,
+    config: &Config,
+    address: &ListenAddress,
+    limiter: &Arc<Limiter>,
+) {
+    // Answering takes a read and a write, so only a few rejected connections get an answer at once.
+    let refusal = match config.limits.action {
+        LimitAction::Disconnect => limiter.acquire_refusal(),
+        LimitAction::Close => None,
+    };
+    let action = match refusal {
+        Some(_) => LimitAction::Disconnect,
+        None => LimitAction::Close,
+    };
+    limiter.record(action);
+    debug!(
+        "Rejected {}, {} ({} closed, {} disconnected so far)",
+        &addr,
+        reason,
+        limiter.closed.load(Ordering::Relaxed),
+        limiter.disconnected.load(Ordering::Relaxed)
+    );
+    if action == LimitAction::Disconnect {
+        let payload = config.limits.message.to_string();
+        let responder = &config.listener(address).responder;
+        if let Err(e) = responder.refuse(socket, addr, &payload).await {
+            debug!("{} error: {}", &addr, e);
         }
     }
-    if res <= 0 {
-        return Err(Box::from("Varint not bigger than 0"));
-    }
-    Ok(res as usize)
 }
@@ -0,0 +1,85 @@
+use crate::version::release_name;
+use serde_json::{Map, Value};
+use std::net::SocketAddr;
+
+pub static DEFAULT_DISCONNECT_MESSAGE: &str = "Your IP address is {ip}";
+
+/// Everything known about a connection by the time a message is rendered.
+pub struct ConnectionInfo<'a> {
+    pub addr: &'a SocketAddr,
+    pub username: &'a str,
+    pub protocol_number: usize,
+    pub hostname: &'a str,
+    pub server_port: u16,
+    pub intent: &'a str,
+}
+
+impl ConnectionInfo<'_> {
+    fn lookup(&self, placeholder: &str) -> Option<String> {
+        match placeholder {
+            "ip" => Some(self.addr.ip().to_string()),
+            "port" => Some(self.addr.port().to_string()),
+            "username" => Some(self.username.to_string()),
+            "protocol" => Some(self.protocol_number.to_string()),
+            "version_name" => Some(
+                i32::try_from(self.protocol_number)
+                    .ok()
+                    .and_then(release_name)
+                    .unwrap_or_else(|| format!("protocol {}", self.protocol_number)),
+            ),
+            "hostname" => Some(self.hostname.to_string()),
+            "server_port" => Some(self.server_port.to_string()),
+            "intent" => Some(self.intent.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Substitutes placeholders in every string of a text component, keeping its styling intact.
+pub fn render_component(template: &Value, info: &ConnectionInfo) -> Value {
+    match template {
+        Value::String(text) => Value::String(render_text(text, info)),
+        Value::Array(components) => Value::Array(
+            components
+                .iter()
+                .map(|component| render_component(component, info))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_component(value, info)))
+                .collect::<Map<String, Value>>(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Replaces `{name}` placeholders in one pass, so substituted values are never expanded again.
+/// Unknown placeholders are left as they are.
+pub fn render_text(template: &str, info: &ConnectionInfo) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        match candidate.find('}') {
+            Some(end) => match info.lookup(&candidate[1..end]) {
+                Some(value) => {
+                    rendered.push_str(&value);
+                    rest = &candidate[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &candidate[1..];
+                }
+            },
+            None => {
+                rendered.push_str(candidate);
+                rest = "";
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
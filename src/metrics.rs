@@ -0,0 +1,351 @@
+use crate::limit::Limiter;
+use crate::listener::ACCEPT_ERROR_BACKOFF;
+use rolling_glass::{is_known_protocol_number, ProtocolNum};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+// https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+static METRICS_PATH: &str = "/metrics";
+static METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+static REQUEST_MAX_LENGTH: usize = 8192usize;
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+static PREFIX: &str = "rolling_looking_glass";
+// Seconds. Handshakes from healthy clients land in the first few buckets.
+static HANDSHAKE_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; 11],
+    sum: f64,
+    count: u64,
+}
+
+/// Counters shared by every connection. Survives config reloads.
+#[derive(Default)]
+pub struct Metrics {
+    connections: Mutex<BTreeMap<&'static str, u64>>,
+    status_responses: AtomicU64,
+    pongs: AtomicU64,
+    login_disconnects: AtomicU64,
+    rejections: Mutex<BTreeMap<String, u64>>,
+    protocols: Mutex<BTreeMap<usize, u64>>,
+    unknown_protocols: AtomicU64,
+    unsupported_protocols: AtomicU64,
+    active: AtomicI64,
+    handshake_duration: Mutex<Histogram>,
+}
+
+impl Metrics {
+    /// Counts the connection as active until the guard is dropped.
+    pub fn track(self: &Arc<Self>) -> ActiveConnection {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection {
+            metrics: self.clone(),
+        }
+    }
+
+    /// `intent` is one of `status`, `login`, `transfer` or `legacy`.
+    pub fn connection(&self, intent: &'static str) {
+        *self.connections.lock().unwrap().entry(intent).or_insert(0) += 1;
+    }
+
+    /// Only supported protocol numbers RollingGlass knows get a series of their own,
+    /// so clients can't add series by making numbers up.
+    pub fn handshake(&self, protocol_number: usize, supported: bool, duration: Duration) {
+        if !supported {
+            self.unsupported_protocols.fetch_add(1, Ordering::Relaxed);
+        } else if is_known_protocol_number(protocol_number as ProtocolNum) {
+            *self
+                .protocols
+                .lock()
+                .unwrap()
+                .entry(protocol_number)
+                .or_insert(0) += 1;
+        } else {
+            self.unknown_protocols.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = duration.as_secs_f64();
+        let mut histogram = self.handshake_duration.lock().unwrap();
+        for (bucket, le) in histogram.buckets.iter_mut().zip(HANDSHAKE_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    pub fn status_response(&self) {
+        self.status_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pong(&self) {
+        self.pongs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn login_disconnect(&self) {
+        self.login_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `reason` labels a series, so it must never carry what the client sent. See `ConnectionError::reason`.
+    pub fn rejection(&self, reason: &str) {
+        let mut rejections = self.rejections.lock().unwrap();
+        match rejections.get_mut(reason) {
+            Some(count) => *count += 1,
+            None => {
+                rejections.insert(reason.to_string(), 1);
+            }
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self, limiter: &Limiter) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "connections_total",
+            "counter",
+            "Connections by intent, counted once the handshake is read",
+        );
+        for (intent, count) in self.connections.lock().unwrap().iter() {
+            sample(&mut out, "connections_total", &[("intent", intent)], *count);
+        }
+        counter(
+            &mut out,
+            "status_responses_total",
+            "Status responses written",
+            self.status_responses.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "pongs_total",
+            "Ping and pong exchanges completed",
+            self.pongs.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "login_disconnects_total",
+            "Disconnect (Login) packets written",
+            self.login_disconnects.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "rejections_total",
+            "counter",
+            "Connections that ended with an error, by reason",
+        );
+        for (reason, count) in self.rejections.lock().unwrap().iter() {
+            sample(&mut out, "rejections_total", &[("reason", reason)], *count);
+        }
+        header(
+            &mut out,
+            "limit_rejections_total",
+            "counter",
+            "Connections over a limit, by the action they got",
+        );
+        sample(
+            &mut out,
+            "limit_rejections_total",
+            &[("action", "close")],
+            limiter.closed.load(Ordering::Relaxed),
+        );
+        sample(
+            &mut out,
+            "limit_rejections_total",
+            &[("action", "disconnect")],
+            limiter.disconnected.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "protocol_versions_total",
+            "counter",
+            "Handshakes by client protocol number, unknown or unsupported ones counted together",
+        );
+        for (protocol_number, count) in self.protocols.lock().unwrap().iter() {
+            sample(
+                &mut out,
+                "protocol_versions_total",
+                &[("protocol", &protocol_number.to_string())],
+                *count,
+            );
+        }
+        sample(
+            &mut out,
+            "protocol_versions_total",
+            &[("protocol", "unknown")],
+            self.unknown_protocols.load(Ordering::Relaxed),
+        );
+        sample(
+            &mut out,
+            "protocol_versions_total",
+            &[("protocol", "unsupported")],
+            self.unsupported_protocols.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "active_connections",
+            "gauge",
+            "Connections being handled",
+        );
+        let _ = writeln!(
+            out,
+            "{}_active_connections {}",
+            PREFIX,
+            self.active.load(Ordering::Relaxed)
+        );
+
+        let histogram = self.handshake_duration.lock().unwrap();
+        header(
+            &mut out,
+            "handshake_duration_seconds",
+            "histogram",
+            "Time from the first byte wait to the parsed handshake",
+        );
+        for (bucket, le) in histogram.buckets.iter().zip(HANDSHAKE_BUCKETS) {
+            sample(
+                &mut out,
+                "handshake_duration_seconds_bucket",
+                &[("le", &le.to_string())],
+                *bucket,
+            );
+        }
+        sample(
+            &mut out,
+            "handshake_duration_seconds_bucket",
+            &[("le", "+Inf")],
+            histogram.count,
+        );
+        let _ = writeln!(
+            out,
+            "{}_handshake_duration_seconds_sum {}",
+            PREFIX, histogram.sum
+        );
+        sample(
+            &mut out,
+            "handshake_duration_seconds_count",
+            &[],
+            histogram.count,
+        );
+        out
+    }
+}
+
+/// Holds a connection in the active gauge until dropped.
+pub struct ActiveConnection {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.metrics.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    sample(out, name, &[], value);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: u64) {
+    let _ = write!(out, "{}_{}", PREFIX, name);
+    if !labels.is_empty() {
+        let labels = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = write!(out, "{{{}}}", labels);
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers `GET /metrics` on `listener` until the task is dropped. Anything else gets a 404.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>, limiter: Arc<Limiter>) {
+    loop {
+        let (mut socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Metrics endpoint error: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            let result =
+                tokio::time::timeout(REQUEST_TIMEOUT, answer(&mut socket, &metrics, &limiter))
+                    .await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Metrics request from {} failed: {}", &peer, e),
+                Err(_) => debug!("Metrics request from {} timed out", &peer),
+            }
+        });
+    }
+}
+
+async fn answer(
+    socket: &mut TcpStream,
+    metrics: &Metrics,
+    limiter: &Limiter,
+) -> Result<(), Box<dyn Error>> {
+    // Only the request line matters, but the headers are read so the client isn't reset mid-request.
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() >= REQUEST_MAX_LENGTH {
+            return Err(Box::from("Request too large"));
+        }
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            return Err(Box::from("Connection closed before the request ended"));
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let (status, content_type, body) = if method == b"GET" && path == METRICS_PATH.as_bytes() {
+        ("200 OK", METRICS_CONTENT_TYPE, metrics.render(limiter))
+    } else {
+        (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            String::from("Not Found\n"),
+        )
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,469 @@
+use crate::codec::{
+    frame, get_bool, get_i64, get_string, get_u16, get_uuid, get_varint, put_i64, put_string,
+    put_varint, string_max_size,
+};
+use crate::error::ConnectionError;
+use crate::forwarding::{ForwardingMode, BUNGEECORD_SERVER_ADDRESS_MAX_LENGTH};
+use crate::frame::{read_frame, Frame};
+use std::fmt;
+use tokio::io::AsyncRead;
+
+// https://minecraft.wiki/w/Java_Edition_protocol/Packets#Handshake
+static HANDSHAKE_PACKET_ID: i32 = 0x00i32;
+static SERVER_ADDRESS_MAX_LENGTH: usize = 255usize;
+// https://minecraft.wiki/w/Java_Edition_protocol/Packets#Status
+static STATUS_REQUEST_PACKET_ID: i32 = 0x00i32;
+static PING_REQUEST_PACKET_ID: i32 = 0x01i32;
+static STATUS_RESPONSE_PACKET_ID: i32 = 0x00i32;
+static PONG_RESPONSE_PACKET_ID: i32 = 0x01i32;
+// Status Request and Ping Request take 1 and 9 bytes, the rest is slack for clients that pad them.
+static STATUS_MAX_FRAME_LENGTH: usize = 256usize;
+// https://minecraft.wiki/w/Java_Edition_protocol/Packets#Login
+static LOGIN_START_PACKET_ID: i32 = 0x00i32;
+static LOGIN_PLUGIN_RESPONSE_PACKET_ID: i32 = 0x02i32;
+static LOGIN_DISCONNECT_PACKET_ID: i32 = 0x00i32;
+static LOGIN_PLUGIN_REQUEST_PACKET_ID: i32 = 0x04i32;
+static USERNAME_MAX_LENGTH: usize = 16usize;
+// Leaves room for the signature data sent by 1.19 to 1.19.2 clients.
+static LOGIN_MAX_FRAME_LENGTH: usize = 8192usize;
+// Velocity's Login Plugin Response carries the whole signed game profile.
+static VELOCITY_LOGIN_MAX_FRAME_LENGTH: usize = 65535usize;
+// The UUID became optional in 1.19.3 and mandatory in 1.20.2.
+static LOGIN_START_UUID_MIN_PROTOCOL_NUMBER: usize = 761usize;
+static LOGIN_START_UUID_REQUIRED_PROTOCOL_NUMBER: usize = 764usize;
+
+/// Where a connection is in the protocol. Packet IDs only mean something within a state.
+///
+/// Configuration (1.20.2 and later) and Play aren't modelled: a client only leaves Login after Login Success, and
+/// every login here ends with Disconnect (Login) instead. A new state needs a variant, packet enums implementing
+/// [`Serverbound`] and [`Clientbound`], and a case in [`Protocol::transition`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Handshaking,
+    Status,
+    Login,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Handshaking => "Handshaking",
+            State::Status => "Status",
+            State::Login => "Login",
+        }
+    }
+
+    /// Names a packet of this state in errors.
+    fn packet(self) -> &'static str {
+        match self {
+            State::Handshaking => "Handshake packet",
+            State::Status => "Status packet",
+            State::Login => "Login packet",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Intent {
+    Status,
+    Login,
+    Transfer,
+}
+
+impl Intent {
+    fn from_id(id: i32) -> Result<Self, ConnectionError> {
+        match id {
+            1 => Ok(Intent::Status),
+            2 => Ok(Intent::Login),
+            3 => Ok(Intent::Transfer),
+            _ => Err(ConnectionError::InvalidIntent(id)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Intent::Status => "status",
+            Intent::Login => "login",
+            Intent::Transfer => "transfer",
+        }
+    }
+
+    /// Transferred clients log in like any other.
+    pub fn next_state(self) -> State {
+        match self {
+            Intent::Status => State::Status,
+            Intent::Login | Intent::Transfer => State::Login,
+        }
+    }
+}
+
+pub struct Handshake {
+    /// Not checked against the known protocol numbers yet.
+    pub protocol_number: i32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub intent: Intent,
+}
+
+pub struct PingRequest {
+    pub payload: i64,
+}
+
+pub struct LoginStart {
+    pub username: String,
+    /// Only sent by 1.19.3 and later clients.
+    pub uuid: Option<u128>,
+}
+
+pub struct LoginPluginResponse {
+    pub message_id: i32,
+    /// `None` when the client didn't understand the channel.
+    pub data: Option<Vec<u8>>,
+}
+
+/// Packets the client may send in one state.
+pub trait Serverbound: Sized {
+    const STATE: State;
+
+    fn decode(protocol: &Protocol, id: i32, body: &mut &[u8]) -> Result<Self, ConnectionError>;
+
+    fn name(&self) -> &'static str;
+}
+
+/// Packets sent to the client in one state.
+pub trait Clientbound {
+    const STATE: State;
+
+    /// Writes the fields to `body` and returns the packet ID.
+    fn encode(&self, body: &mut Vec<u8>) -> i32;
+
+    fn name(&self) -> &'static str;
+}
+
+pub enum HandshakingServerbound {
+    Handshake(Handshake),
+}
+
+impl Serverbound for HandshakingServerbound {
+    const STATE: State = State::Handshaking;
+
+    fn decode(protocol: &Protocol, id: i32, body: &mut &[u8]) -> Result<Self, ConnectionError> {
+        match id {
+            id if id == HANDSHAKE_PACKET_ID => Ok(HandshakingServerbound::Handshake(Handshake {
+                protocol_number: get_varint(body)?,
+                server_address: get_string(
+                    body,
+                    protocol.server_address_max_length(),
+                    "Server address",
+                )?,
+                server_port: get_u16(body)?,
+                intent: Intent::from_id(get_varint(body)?)?,
+            })),
+            id => Err(ConnectionError::UnknownPacketId {
+                packet: Self::STATE.name(),
+                id,
+            }),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            HandshakingServerbound::Handshake(_) => "Handshake",
+        }
+    }
+}
+
+pub enum StatusServerbound {
+    StatusRequest,
+    PingRequest(PingRequest),
+}
+
+impl Serverbound for StatusServerbound {
+    const STATE: State = State::Status;
+
+    fn decode(_: &Protocol, id: i32, body: &mut &[u8]) -> Result<Self, ConnectionError> {
+        match id {
+            id if id == STATUS_REQUEST_PACKET_ID => Ok(StatusServerbound::StatusRequest),
+            id if id == PING_REQUEST_PACKET_ID => Ok(StatusServerbound::PingRequest(PingRequest {
+                payload: get_i64(body)?,
+            })),
+            id => Err(ConnectionError::UnknownPacketId {
+                packet: Self::STATE.name(),
+                id,
+            }),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            StatusServerbound::StatusRequest => "Status Request",
+            StatusServerbound::PingRequest(_) => "Ping Request",
+        }
+    }
+}
+
+pub enum StatusClientbound<'a> {
+    StatusResponse(&'a str),
+    PongResponse(i64),
+}
+
+impl Clientbound for StatusClientbound<'_> {
+    const STATE: State = State::Status;
+
+    fn encode(&self, body: &mut Vec<u8>) -> i32 {
+        match self {
+            StatusClientbound::StatusResponse(json) => {
+                put_string(body, json);
+                STATUS_RESPONSE_PACKET_ID
+            }
+            StatusClientbound::PongResponse(payload) => {
+                put_i64(body, *payload);
+                PONG_RESPONSE_PACKET_ID
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            StatusClientbound::StatusResponse(_) => "Status Response",
+            StatusClientbound::PongResponse(_) => "Pong Response",
+        }
+    }
+}
+
+pub enum LoginServerbound {
+    LoginStart(LoginStart),
+    LoginPluginResponse(LoginPluginResponse),
+}
+
+impl Serverbound for LoginServerbound {
+    const STATE: State = State::Login;
+
+    fn decode(protocol: &Protocol, id: i32, body: &mut &[u8]) -> Result<Self, ConnectionError> {
+        match id {
+            id if id == LOGIN_START_PACKET_ID => {
+                let username = get_string(body, USERNAME_MAX_LENGTH, "Username")?;
+                if username.is_empty() {
+                    return Err(ConnectionError::InvalidUsernameLength(0));
+                }
+                let uuid = if protocol.protocol_number >= LOGIN_START_UUID_REQUIRED_PROTOCOL_NUMBER
+                    || (protocol.protocol_number >= LOGIN_START_UUID_MIN_PROTOCOL_NUMBER
+                        && get_bool(body)?)
+                {
+                    Some(get_uuid(body)?)
+                } else {
+                    None
+                };
+                Ok(LoginServerbound::LoginStart(LoginStart { username, uuid }))
+            }
+            id if id == LOGIN_PLUGIN_RESPONSE_PACKET_ID => {
+                let message_id = get_varint(body)?;
+                let data = if get_bool(body)? {
+                    Some(body.to_vec())
+                } else {
+                    None
+                };
+                Ok(LoginServerbound::LoginPluginResponse(LoginPluginResponse {
+                    message_id,
+                    data,
+                }))
+            }
+            id => Err(ConnectionError::UnknownPacketId {
+                packet: Self::STATE.name(),
+                id,
+            }),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LoginServerbound::LoginStart(_) => "Login Start",
+            LoginServerbound::LoginPluginResponse(_) => "Login Plugin Response",
+        }
+    }
+}
+
+pub enum LoginClientbound<'a> {
+    Disconnect(&'a str),
+    LoginPluginRequest {
+        message_id: i32,
+        channel: &'a str,
+        data: &'a [u8],
+    },
+}
+
+impl Clientbound for LoginClientbound<'_> {
+    const STATE: State = State::Login;
+
+    fn encode(&self, body: &mut Vec<u8>) -> i32 {
+        match self {
+            LoginClientbound::Disconnect(reason) => {
+                put_string(body, reason);
+                LOGIN_DISCONNECT_PACKET_ID
+            }
+            LoginClientbound::LoginPluginRequest {
+                message_id,
+                channel,
+                data,
+            } => {
+                put_varint(body, *message_id);
+                put_string(body, channel);
+                body.extend_from_slice(data);
+                LOGIN_PLUGIN_REQUEST_PACKET_ID
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LoginClientbound::Disconnect(_) => "Disconnect (Login)",
+            LoginClientbound::LoginPluginRequest { .. } => "Login Plugin Request",
+        }
+    }
+}
+
+/// The protocol side of one connection: which state it is in, and what that state accepts.
+pub struct Protocol {
+    state: State,
+    forwarding: ForwardingMode,
+    protocol_number: usize,
+}
+
+impl Protocol {
+    pub fn new(forwarding: ForwardingMode) -> Self {
+        Protocol {
+            state: State::Handshaking,
+            forwarding,
+            protocol_number: 0,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Moves on to the state the handshake asked for. Any other transition is an error.
+    pub fn transition(
+        &mut self,
+        next: State,
+        protocol_number: usize,
+    ) -> Result<(), ConnectionError> {
+        match (self.state, next) {
+            (State::Handshaking, State::Status) | (State::Handshaking, State::Login) => {
+                self.state = next;
+                self.protocol_number = protocol_number;
+                Ok(())
+            }
+            (from, to) => Err(ConnectionError::InvalidTransition { from, to }),
+        }
+    }
+
+    fn server_address_max_length(&self) -> usize {
+        if self.forwarding == ForwardingMode::Bungeecord {
+            BUNGEECORD_SERVER_ADDRESS_MAX_LENGTH
+        } else {
+            SERVER_ADDRESS_MAX_LENGTH
+        }
+    }
+
+    /// The largest frame the client may send in the current state, packet ID included.
+    pub fn max_frame_length(&self) -> usize {
+        match self.state {
+            // Packet ID, protocol number, server address, server port and intent.
+            State::Handshaking => 1 + 5 + string_max_size(self.server_address_max_length()) + 2 + 5,
+            State::Status => STATUS_MAX_FRAME_LENGTH,
+            State::Login if self.forwarding == ForwardingMode::Velocity => {
+                VELOCITY_LOGIN_MAX_FRAME_LENGTH
+            }
+            State::Login => LOGIN_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Reads the next packet, which must belong to the current state.
+    pub async fn read<P: Serverbound, R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+    ) -> Result<P, ConnectionError> {
+        self.expect(P::STATE, P::STATE.packet())?;
+        let frame = read_frame(reader, self.max_frame_length(), self.state.packet()).await?;
+        self.decode(&frame)
+    }
+
+    pub fn decode<P: Serverbound>(&self, frame: &Frame) -> Result<P, ConnectionError> {
+        self.expect(P::STATE, P::STATE.packet())?;
+        P::decode(self, frame.id, &mut frame.body())
+    }
+
+    /// Frames a packet, unless the current state doesn't have it.
+    pub fn encode<P: Clientbound>(&self, packet: &P) -> Result<Vec<u8>, ConnectionError> {
+        self.expect(P::STATE, packet.name())?;
+        let mut body = Vec::new();
+        let id = packet.encode(&mut body);
+        Ok(frame(id, &body))
+    }
+
+    fn expect(&self, state: State, packet: &'static str) -> Result<(), ConnectionError> {
+        if state == self.state {
+            Ok(())
+        } else {
+            Err(ConnectionError::UnexpectedPacket {
+                state: self.state,
+                packet,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn packets_of_the_current_state() {
+        let mut body = Vec::new();
+        put_i64(&mut body, 42);
+        let ping = frame(PING_REQUEST_PACKET_ID, &body);
+
+        let mut protocol = Protocol::new(ForwardingMode::None);
+        assert!(matches!(
+            protocol
+                .read::<StatusServerbound, _>(&mut ping.as_slice())
+                .await,
+            Err(ConnectionError::UnexpectedPacket {
+                state: State::Handshaking,
+                packet: "Status packet",
+            })
+        ));
+        protocol.transition(State::Status, 767).unwrap();
+        assert!(matches!(
+            protocol.read(&mut ping.as_slice()).await,
+            Ok(StatusServerbound::PingRequest(PingRequest { payload: 42 }))
+        ));
+        assert_eq!(
+            protocol
+                .encode(&StatusClientbound::PongResponse(42))
+                .unwrap(),
+            ping
+        );
+        assert!(matches!(
+            protocol.encode(&LoginClientbound::Disconnect("{}")),
+            Err(ConnectionError::UnexpectedPacket {
+                state: State::Status,
+                packet: "Disconnect (Login)",
+            })
+        ));
+        assert!(matches!(
+            protocol.transition(State::Login, 767),
+            Err(ConnectionError::InvalidTransition {
+                from: State::Status,
+                to: State::Login,
+            })
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::cidr::Cidr;
+use crate::error::ConnectionError;
+use crate::listener::Socket;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+static V1_MAX_LENGTH: usize = 107usize;
+static V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+// Generous room for TLVs, still small enough to not be abused.
+static V2_MAX_ADDRESS_LENGTH: usize = 1024usize;
+
+pub struct ProxyConfig {
+    /// Peers allowed to send a PROXY header. Empty means no peer is trusted.
+    pub trusted: Vec<Cidr>,
+}
+
+impl ProxyConfig {
+    pub fn is_trusted(&self, addr: &SocketAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(&addr.ip()))
+    }
+}
+
+/// Reads the mandatory PROXY header and returns the address of the real client.
+/// LOCAL and UNKNOWN headers, used by health checks, keep the peer address.
+pub async fn accept_proxy_header(
+    socket: &mut Socket,
+    addr: &SocketAddr,
+    config: &ProxyConfig,
+) -> Result<SocketAddr, ConnectionError> {
+    if !socket.get_ref().is_local() && !config.is_trusted(addr) {
+        debug!("{} is not a trusted proxy", &addr);
+        return Err(ConnectionError::Proxy("Untrusted proxy"));
+    }
+    match read_proxy_header(socket).await? {
+        Some(source) => {
+            debug!("{} is proxying {}", &addr, &source);
+            Ok(source)
+        }
+        None => Ok(*addr),
+    }
+}
+
+async fn read_proxy_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<SocketAddr>, ConnectionError> {
+    match reader.read_u8().await? {
+        b'P' => read_v1(reader).await,
+        0x0D => read_v2(reader).await,
+        _ => Err(ConnectionError::Proxy("Missing PROXY header")),
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(
+    socket: &mut R,
+) -> Result<Option<SocketAddr>, ConnectionError> {
+    let mut line = vec![b'P'];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ConnectionError::TooLong {
+                what: "PROXY header",
+                length: line.len(),
+                max: V1_MAX_LENGTH,
+            });
+        }
+        line.push(socket.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, ConnectionError> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, source_port, _] => {
+            let ip = IpAddr::from_str(source)
+                .map_err(|_| ConnectionError::Proxy("Malformed PROXY header"))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(ConnectionError::Proxy(
+                    "PROXY header address family mismatch",
+                ));
+            }
+            let port = u16::from_str(source_port)
+                .map_err(|_| ConnectionError::Proxy("Malformed PROXY header"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(ConnectionError::Proxy("Malformed PROXY header")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(
+    socket: &mut R,
+) -> Result<Option<SocketAddr>, ConnectionError> {
+    let mut header = [0u8; 16];
+    header[0] = 0x0D;
+    socket.read_exact(&mut header[1..]).await?;
+    if header[..12] != V2_SIGNATURE {
+        return Err(ConnectionError::Proxy("Malformed PROXY header"));
+    }
+    if header[12] >> 4 != 2 {
+        return Err(ConnectionError::Proxy("Unsupported PROXY protocol version"));
+    }
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    if length > V2_MAX_ADDRESS_LENGTH {
+        return Err(ConnectionError::TooLong {
+            what: "PROXY header",
+            length,
+            max: V2_MAX_ADDRESS_LENGTH,
+        });
+    }
+    let mut addresses = vec![0u8; length];
+    socket.read_exact(&mut addresses).await?;
+
+    match header[12] & 0x0F {
+        // LOCAL
+        0x00 => return Ok(None),
+        // PROXY
+        0x01 => {}
+        _ => return Err(ConnectionError::Proxy("Unknown PROXY command")),
+    }
+    // Only the source address and port matter, TLVs are skipped.
+    match header[13] {
+        // TCP over IPv4
+        0x11 if length >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // TCP over IPv6
+        0x21 if length >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x11 | 0x21 => Err(ConnectionError::Proxy("PROXY header too short")),
+        // UNSPEC, UDP and Unix sockets carry nothing usable
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut header: &[u8]) -> Result<Option<SocketAddr>, ConnectionError> {
+        read_proxy_header(&mut header).await
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_headers() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.0.2.7 198.51.100.1 51234 25565\r\n")
+                .await
+                .unwrap(),
+            Some("192.0.2.7:51234".parse().unwrap())
+        );
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 25565\r\n")
+                .await
+                .unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+        assert!(matches!(
+            read(b"PROXY TCP4 2001:db8::7 2001:db8::1 51234 25565\r\n").await,
+            Err(ConnectionError::Proxy(_))
+        ));
+        assert!(matches!(
+            read(b"PROXY TCP4 192.0.2.7 198.51.100.1 65536 25565\r\n").await,
+            Err(ConnectionError::Proxy(_))
+        ));
+        let long = format!("PROXY UNKNOWN {}\r\n", "a".repeat(V1_MAX_LENGTH));
+        assert!(matches!(
+            read(long.as_bytes()).await,
+            Err(ConnectionError::TooLong { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn v2_headers() {
+        let mut tcp4 = vec![192, 0, 2, 7, 198, 51, 100, 1];
+        tcp4.extend_from_slice(&51234u16.to_be_bytes());
+        tcp4.extend_from_slice(&25565u16.to_be_bytes());
+        assert_eq!(
+            read(&v2(0x01, 0x11, &tcp4)).await.unwrap(),
+            Some("192.0.2.7:51234".parse().unwrap())
+        );
+        // Health checks send LOCAL, whatever addresses come with it.
+        assert_eq!(read(&v2(0x00, 0x11, &tcp4)).await.unwrap(), None);
+
+        let mut tcp6 = "2001:db8::7".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        tcp6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        tcp6.extend_from_slice(&51234u16.to_be_bytes());
+        tcp6.extend_from_slice(&25565u16.to_be_bytes());
+        assert_eq!(
+            read(&v2(0x01, 0x21, &tcp6)).await.unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+
+        assert!(matches!(
+            read(&v2(0x01, 0x11, &tcp4[..8])).await,
+            Err(ConnectionError::Proxy("PROXY header too short"))
+        ));
+        assert!(matches!(
+            read(&v2(0x02, 0x11, &tcp4)).await,
+            Err(ConnectionError::Proxy("Unknown PROXY command"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn v2_lengths() {
+        let mut header = v2(0x01, 0x11, &[]);
+        header[14..16].copy_from_slice(&((V2_MAX_ADDRESS_LENGTH + 1) as u16).to_be_bytes());
+        assert!(matches!(
+            read(&header).await,
+            Err(ConnectionError::TooLong { .. })
+        ));
+        // Promises 12 bytes of addresses, then ends.
+        let mut header = v2(0x01, 0x11, &[192, 0, 2, 7]);
+        header[14..16].copy_from_slice(&12u16.to_be_bytes());
+        assert!(matches!(
+            read(&header).await,
+            Err(ConnectionError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn bad_signatures() {
+        let mut header = v2(0x01, 0x11, &[0; 12]);
+        header[5] = 0xFF;
+        assert!(matches!(
+            read(&header).await,
+            Err(ConnectionError::Proxy("Malformed PROXY header"))
+        ));
+        let mut header = v2(0x01, 0x11, &[0; 12]);
+        header[12] = 0x11;
+        assert!(matches!(
+            read(&header).await,
+            Err(ConnectionError::Proxy("Unsupported PROXY protocol version"))
+        ));
+        assert!(matches!(
+            read(b"GET / HTTP/1.1\r\n").await,
+            Err(ConnectionError::Proxy("Missing PROXY header"))
+        ));
+        assert!(matches!(
+            read(b"PRXY TCP4 192.0.2.7 198.51.100.1 51234 25565\r\n").await,
+            Err(ConnectionError::Proxy("Malformed PROXY header"))
+        ));
+    }
+
+    #[test]
+    fn trusted_peers() {
+        let config = ProxyConfig {
+            trusted: vec![Cidr::from_str("10.0.0.0/8").unwrap()],
+        };
+        assert!(config.is_trusted(&"10.1.2.3:51234".parse().unwrap()));
+        assert!(!config.is_trusted(&"192.0.2.7:51234".parse().unwrap()));
+        let config = ProxyConfig {
+            trusted: Vec::new(),
+        };
+        assert!(!config.is_trusted(&"10.1.2.3:51234".parse().unwrap()));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::access::AccessAction;
+use crate::access_log::AccessRecord;
+use crate::error::ConnectionError;
+use crate::forwarding::{
+    parse_bungeecord, velocity_forwarded_ip, ForwardingConfig, ForwardingMode,
+    VELOCITY_MIN_PROTOCOL_NUMBER,
+};
+use crate::handler::{DefaultHandler, Handler, HandshakeContext};
+use crate::legacy::{read_legacy_ping, write_legacy_status, LegacyStatus, LEGACY_PING_PACKET_ID};
+use crate::listener::AsyncStream;
+use crate::metrics::Metrics;
+use crate::protocol::{
+    HandshakingServerbound, Intent, LoginClientbound, LoginServerbound, Protocol, State,
+    StatusClientbound, StatusServerbound,
+};
+use crate::timeout::{within, Timeouts};
+use crate::version::VersionPolicy;
+use crate::vhost::{normalize_hostname, Profile, VirtualHosts};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tracing::{debug, info};
+
+// https://minecraft.wiki/w/Java_Edition_protocol/Packets#Transfer_(configuration)
+// Transfers were introduced in 1.20.5.
+static TRANSFER_MIN_PROTOCOL_NUMBER: usize = 766usize;
+
+/// What the handshake told about the client, once validated.
+struct Handshake {
+    protocol_number: usize,
+    hostname: String,
+    server_port: u16,
+    intent: Intent,
+    /// The peer address, or the client address forwarded by BungeeCord.
+    client_addr: SocketAddr,
+    forwarded_by_bungeecord: bool,
+    /// Unsupported clients are only told which versions work.
+    supported: bool,
+}
+
+/// Applies what the access lists decided to the profile, or returns `None` when the connection must be closed.
+fn restrict<'a>(
+    profile: &'a Profile,
+    access: Option<&AccessAction>,
+    status: bool,
+) -> Option<Cow<'a, Profile>> {
+    match access {
+        None => Some(Cow::Borrowed(profile)),
+        Some(AccessAction::Drop) => None,
+        Some(AccessAction::Disconnect(_)) if status => None,
+        Some(AccessAction::Disconnect(message)) => {
+            let mut profile = profile.clone();
+            profile.disconnect_message = message.clone();
+            Some(Cow::Owned(profile))
+        }
+        Some(AccessAction::Maintenance(message)) => {
+            let mut profile = profile.clone();
+            profile.status.description = message.clone();
+            profile.disconnect_message = message.clone();
+            Some(Cow::Owned(profile))
+        }
+    }
+}
+
+/// Answers connections with a status response, or a Disconnect once the client logs in.
+/// Any tokio service can embed one and hand it the streams it accepts.
+#[derive(Clone, Debug)]
+pub struct Responder<H = DefaultHandler> {
+    pub virtual_hosts: VirtualHosts,
+    pub forwarding: ForwardingConfig,
+    pub timeouts: Timeouts,
+    pub versions: VersionPolicy,
+    pub handler: H,
+}
+
+impl Responder {
+    /// Shows `profile` to every connection, without forwarding and with the default timeouts.
+    pub fn new(profile: Profile) -> Self {
+        Responder {
+            virtual_hosts: VirtualHosts::new(profile),
+            forwarding: ForwardingConfig::default(),
+            timeouts: Timeouts::default(),
+            versions: VersionPolicy::default(),
+            handler: DefaultHandler,
+        }
+    }
+}
+
+impl<H: Handler> Responder<H> {
+    /// Lets `handler` decide what connections see, instead of the profile alone.
+    pub fn with_handler<T: Handler>(self, handler: T) -> Responder<T> {
+        Responder {
+            virtual_hosts: self.virtual_hosts,
+            forwarding: self.forwarding,
+            timeouts: self.timeouts,
+            versions: self.versions,
+            handler,
+        }
+    }
+
+    /// Serves one connection the way the service does, without access lists, metrics or an access log.
+    pub async fn respond<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        addr: &SocketAddr,
+    ) -> Result<(), ConnectionError> {
+        let metrics = Metrics::default();
+        let mut record = AccessRecord::new(0, String::new(), addr);
+        self.respond_with(socket, addr, None, &metrics, &mut record)
+            .await
+    }
+
+    /// Serves one connection. `access` is what the access lists decided for the peer, `record` is filled in as it goes.
+    pub async fn respond_with<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        addr: &SocketAddr,
+        access: Option<&AccessAction>,
+        metrics: &Metrics,
+        record: &mut AccessRecord,
+    ) -> Result<(), ConnectionError> {
+        let timeouts = &self.timeouts;
+        let mut byte: u8 = 255u8;
+        let started = Instant::now();
+
+        // Pre-1.7 clients open with 0xFE. Vanilla servers treat that first byte as a legacy ping too.
+        within(timeouts.handshake, "Handshake", async {
+            if let Some(first) = socket.fill_buf().await?.first() {
+                byte = *first;
+            }
+            Ok::<(), ConnectionError>(())
+        })
+        .await?;
+        if byte == LEGACY_PING_PACKET_ID {
+            debug!("{} sent a legacy ping", &addr);
+            metrics.connection("legacy");
+            record.intent = Some("legacy");
+            let ping = within(
+                timeouts.handshake,
+                "Legacy ping",
+                read_legacy_ping(socket, addr),
+            )
+            .await?;
+            // 1.6 names the host it connected to, older clients only ever see the default profile.
+            let profile = match ping.hostname() {
+                Some(hostname) => {
+                    let hostname = normalize_hostname(hostname);
+                    debug!("Read legacy server address {:?} from {}", &hostname, &addr);
+                    record.hostname = Some(hostname.clone());
+                    self.virtual_hosts.select(&hostname)
+                }
+                None => self.virtual_hosts.default_profile(),
+            };
+            let profile = restrict(profile, access, true).ok_or(ConnectionError::Denied)?;
+            let status = &profile.status;
+            let motd = status.motd_plain();
+            let legacy_status = LegacyStatus {
+                brand: &status.brand,
+                motd: &motd,
+                online: status.online_players,
+                max: status.max_players,
+            };
+            within(
+                timeouts.write,
+                "Write",
+                write_legacy_status(socket, addr, &ping, &legacy_status),
+            )
+            .await?;
+            metrics.status_response();
+            return Ok(());
+        }
+
+        let mut protocol = Protocol::new(self.forwarding.mode);
+        let handshake = within(
+            timeouts.handshake,
+            "Handshake",
+            self.read_handshake(socket, &protocol, addr),
+        )
+        .await?;
+        metrics.handshake(
+            handshake.protocol_number,
+            handshake.supported,
+            started.elapsed(),
+        );
+        metrics.connection(handshake.intent.name());
+        record.set_peer(&handshake.client_addr);
+        record.hostname = Some(handshake.hostname.clone());
+        record.server_port = Some(handshake.server_port);
+        record.protocol_number = Some(handshake.protocol_number);
+        record.intent = Some(handshake.intent.name());
+        protocol.transition(handshake.intent.next_state(), handshake.protocol_number)?;
+        let addr = &handshake.client_addr;
+        let profile = restrict(
+            self.virtual_hosts.select(&handshake.hostname),
+            access,
+            protocol.state() == State::Status,
+        )
+        .ok_or(ConnectionError::Denied)?;
+
+        match handshake.intent {
+            Intent::Status => {
+                within(
+                    timeouts.status,
+                    "Status",
+                    self.handle_status(socket, &protocol, addr, &handshake, &profile, metrics),
+                )
+                .await?;
+                metrics.pong();
+            }
+            Intent::Login | Intent::Transfer => {
+                within(
+                    timeouts.handshake,
+                    "Login",
+                    self.handle_login(socket, &protocol, addr, &handshake, &profile, record),
+                )
+                .await?;
+                metrics.login_disconnect();
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns away a connection over a limit with `payload` as the Disconnect (Login) message.
+    /// Only clients logging in show it, so status pings are closed once their handshake is read.
+    pub async fn refuse<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        addr: &SocketAddr,
+        payload: &str,
+    ) -> Result<(), ConnectionError> {
+        let mut protocol = Protocol::new(self.forwarding.mode);
+        let HandshakingServerbound::Handshake(packet) =
+            within(self.timeouts.handshake, "Handshake", protocol.read(socket)).await?;
+        if packet.intent == Intent::Status {
+            debug!("Closing the status connection of {}", &addr);
+            socket.shutdown().await?;
+            return Ok(());
+        }
+        protocol.transition(State::Login, packet.protocol_number.max(0) as usize)?;
+        self.write_disconnect(socket, &protocol, addr, payload)
+            .await
+    }
+
+    async fn read_handshake<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        protocol: &Protocol,
+        addr: &SocketAddr,
+    ) -> Result<Handshake, ConnectionError> {
+        let HandshakingServerbound::Handshake(packet) = protocol.read(socket).await?;
+
+        let protocol_number = packet.protocol_number;
+        if protocol_number < 0 {
+            debug!(
+                "{} sent a handshake packet that has a negative protocol number",
+                &addr
+            );
+            return Err(ConnectionError::UnknownProtocol(protocol_number));
+        }
+        let supported = self.versions.supports(protocol_number);
+        if !supported {
+            debug!(
+                "{} sent a handshake packet that has unsupported protocol number {}",
+                &addr, protocol_number
+            );
+        }
+        let protocol_number = protocol_number as usize;
+        debug!("Read protocol number {} from {}", protocol_number, &addr);
+
+        let mut hostname = packet.server_address;
+        debug!("Read server address {:?} from {}", &hostname, &addr);
+
+        let mut client_addr = *addr;
+        let mut forwarded_by_bungeecord = false;
+        if self.forwarding.mode == ForwardingMode::Bungeecord {
+            if let Some(forwarded) = parse_bungeecord(&hostname)? {
+                forwarded_by_bungeecord = true;
+                debug!(
+                    "{} forwarded {} with UUID {}",
+                    &addr, &forwarded.ip, &forwarded.uuid
+                );
+                client_addr.set_ip(forwarded.ip);
+                hostname = forwarded.hostname;
+            }
+        }
+        let addr = &client_addr;
+        let hostname = normalize_hostname(&hostname);
+        debug!(
+            "Read server port number {} and intent {} from {}",
+            packet.server_port,
+            packet.intent.name(),
+            &addr
+        );
+
+        if packet.intent == Intent::Transfer {
+            if protocol_number < TRANSFER_MIN_PROTOCOL_NUMBER {
+                debug!(
+                    "{} sent a Transfer intent with protocol number {} which predates transfers",
+                    &addr, protocol_number
+                );
+                return Err(ConnectionError::TransferUnsupported(protocol_number));
+            }
+            info!("{} arrived through a transfer", &addr);
+        }
+
+        Ok(Handshake {
+            protocol_number,
+            hostname,
+            server_port: packet.server_port,
+            intent: packet.intent,
+            client_addr,
+            forwarded_by_bungeecord,
+            supported,
+        })
+    }
+
+    async fn handle_status<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        protocol: &Protocol,
+        addr: &SocketAddr,
+        handshake: &Handshake,
+        profile: &Profile,
+        metrics: &Metrics,
+    ) -> Result<(), ConnectionError> {
+        // Clients may skip the Status Request, or send several packets at once.
+        let mut responded = false;
+        loop {
+            match protocol.read(socket).await? {
+                StatusServerbound::StatusRequest if !responded => {
+                    debug!("Read Status Request from {}", &addr);
+                    let context = HandshakeContext {
+                        addr,
+                        hostname: &handshake.hostname,
+                        server_port: handshake.server_port,
+                        protocol_number: handshake.protocol_number,
+                        intent: handshake.intent,
+                        username: None,
+                        profile,
+                    };
+                    let payload = if handshake.supported {
+                        self.handler.status(&context).await
+                    } else {
+                        self.versions.incompatible_status(
+                            profile.status.to_json(handshake.protocol_number, addr),
+                        )
+                    };
+                    let payload = payload.to_string();
+                    debug!("Writing Status Response to {}", &addr);
+                    let packet = protocol.encode(&StatusClientbound::StatusResponse(&payload))?;
+                    self.write_packet(socket, &packet).await?;
+                    metrics.status_response();
+                    responded = true;
+                    debug!("Waiting for Ping Request from {}", &addr);
+                }
+                StatusServerbound::PingRequest(ping) => {
+                    debug!("Writing Ping Response to {}", &addr);
+                    let packet = protocol.encode(&StatusClientbound::PongResponse(ping.payload))?;
+                    self.write_packet(socket, &packet).await?;
+                    return Ok(());
+                }
+                packet => {
+                    return Err(ConnectionError::UnexpectedPacket {
+                        state: protocol.state(),
+                        packet: packet.name(),
+                    })
+                }
+            }
+        }
+    }
+
+    async fn handle_login<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        protocol: &Protocol,
+        addr: &SocketAddr,
+        handshake: &Handshake,
+        profile: &Profile,
+        record: &mut AccessRecord,
+    ) -> Result<(), ConnectionError> {
+        let forwarding = &self.forwarding;
+
+        if forwarding.mode == ForwardingMode::Bungeecord && !handshake.forwarded_by_bungeecord {
+            debug!("{} logged in without BungeeCord forwarding data", &addr);
+            return Err(ConnectionError::Forwarding(
+                "Missing BungeeCord forwarding data",
+            ));
+        }
+
+        // Login Start may not even be laid out the way this build expects, so it isn't read.
+        if !handshake.supported {
+            let payload = self.versions.message().to_string();
+            return self
+                .write_disconnect(socket, protocol, addr, &payload)
+                .await;
+        }
+
+        let login_start = match protocol.read(socket).await? {
+            LoginServerbound::LoginStart(packet) => packet,
+            packet => {
+                return Err(ConnectionError::UnexpectedPacket {
+                    state: protocol.state(),
+                    packet: packet.name(),
+                })
+            }
+        };
+        let username = login_start.username;
+        debug!("Read username {} from {}", &username, &addr);
+        record.username = Some(username.clone());
+        if let Some(uuid) = login_start.uuid {
+            debug!("Read UUID {:032x} from {}", uuid, &addr);
+        }
+
+        let protocol_number = handshake.protocol_number;
+        let mut client_addr = *addr;
+        if forwarding.mode == ForwardingMode::Velocity {
+            if protocol_number < VELOCITY_MIN_PROTOCOL_NUMBER {
+                return Err(ConnectionError::VelocityUnsupported(protocol_number));
+            }
+            let forwarded_ip =
+                velocity_forwarded_ip(socket, protocol, addr, &forwarding.velocity_secret).await?;
+            debug!("{} forwarded {}", &addr, &forwarded_ip);
+            client_addr.set_ip(forwarded_ip);
+            record.set_peer(&client_addr);
+        }
+        let addr = &client_addr;
+
+        // Immediately send Disconnect (Login), the rest of the buffer is ignored.
+        let context = HandshakeContext {
+            addr,
+            hostname: &handshake.hostname,
+            server_port: handshake.server_port,
+            protocol_number,
+            intent: handshake.intent,
+            username: Some(&username),
+            profile,
+        };
+        let payload = self.handler.disconnect(&context).await.to_string();
+        self.write_disconnect(socket, protocol, addr, &payload)
+            .await
+    }
+
+    async fn write_disconnect<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        protocol: &Protocol,
+        addr: &SocketAddr,
+        payload: &str,
+    ) -> Result<(), ConnectionError> {
+        debug!("Writing Disconnect (Login) packet to {}", &addr);
+        let packet = protocol.encode(&LoginClientbound::Disconnect(payload))?;
+        self.write_packet(socket, &packet).await?;
+        socket.shutdown().await?;
+        Ok(())
+    }
+
+    async fn write_packet<S: AsyncStream>(
+        &self,
+        socket: &mut S,
+        packet: &[u8],
+    ) -> Result<(), ConnectionError> {
+        within(self.timeouts.write, "Write", async {
+            socket.write_all(packet).await?;
+            Ok::<(), ConnectionError>(())
+        })
+        .await
+    }
+}
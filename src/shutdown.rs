@@ -0,0 +1,36 @@
+use tracing::warn;
+
+/// Resolves with the name of the first shutdown signal received.
+/// SIGTERM is only listened for on Unix, Ctrl-C everywhere.
+pub async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    let mut terminate =
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(terminate) => Some(terminate),
+            Err(e) => {
+                warn!("Unable to listen for SIGTERM: {}", e);
+                None
+            }
+        };
+    #[cfg(unix)]
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(terminate) => terminate.recv().await,
+            None => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminated = std::future::pending::<Option<()>>();
+
+    let interrupted = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Unable to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        _ = interrupted => "SIGINT",
+        _ = terminated => "SIGTERM",
+    }
+}
@@ -0,0 +1,179 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path;
+
+// https://minecraft.wiki/w/Java_Edition_protocol/Server_List_Ping#Status_Response
+static FAVICON_SIZE: u32 = 64u32;
+static PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+static EMPTY_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+#[derive(Clone, Debug)]
+pub struct SamplePlayer {
+    pub name: String,
+    pub id: String,
+}
+
+/// Where the Status Response shows the visitor's own IP address.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShowIp {
+    #[default]
+    None,
+    Motd,
+    Sample,
+    Both,
+}
+
+#[derive(Clone, Debug)]
+pub struct StatusConfig {
+    pub brand: String,
+    pub description: Value,
+    pub max_players: usize,
+    pub online_players: usize,
+    pub sample: Vec<SamplePlayer>,
+    pub favicon: Option<String>,
+    pub enforces_secure_chat: bool,
+    pub show_ip: ShowIp,
+}
+
+impl StatusConfig {
+    pub fn new(brand: String) -> Self {
+        StatusConfig {
+            brand,
+            description: json!({ "text": "" }),
+            max_players: 0,
+            online_players: 0,
+            sample: Vec::new(),
+            favicon: None,
+            enforces_secure_chat: false,
+            show_ip: ShowIp::None,
+        }
+    }
+
+    pub fn to_json(&self, protocol_number: usize, addr: &SocketAddr) -> Value {
+        let ip_text = format!("Your IP address is {}", addr.ip());
+        let mut sample: Vec<Value> = self
+            .sample
+            .iter()
+            .map(|player| json!({ "name": player.name, "id": player.id }))
+            .collect();
+        let mut description = self.description.clone();
+        if matches!(self.show_ip, ShowIp::Sample | ShowIp::Both) {
+            sample.insert(0, json!({ "name": ip_text, "id": EMPTY_UUID }));
+        }
+        if matches!(self.show_ip, ShowIp::Motd | ShowIp::Both) {
+            let separator = if flatten_component(&description).is_empty() {
+                ""
+            } else {
+                "\n"
+            };
+            description = json!({
+                "text": "",
+                "extra": [description, { "text": format!("{}{}", separator, ip_text) }]
+            });
+        }
+        let mut payload = json!({
+            "version": json!({
+                "name": self.brand,
+                "protocol": protocol_number
+            }),
+            "players": json!({
+                "max": self.max_players,
+                "online": self.online_players,
+                "sample": sample
+            }),
+            "description": description,
+            "enforcesSecureChat": self.enforces_secure_chat
+        });
+        if let Some(favicon) = &self.favicon {
+            payload["favicon"] = Value::from(favicon.as_str());
+        }
+        payload
+    }
+
+    /// The MOTD flattened to legacy text, falling back to the brand when it's empty.
+    pub fn motd_plain(&self) -> String {
+        let motd = flatten_component(&self.description);
+        if motd.is_empty() {
+            self.brand.clone()
+        } else {
+            motd
+        }
+    }
+}
+
+/// Accepts either a JSON text component or plain text, which may use legacy section sign codes.
+pub fn parse_component(text: &str) -> Value {
+    match serde_json::from_str::<Value>(text) {
+        Ok(component @ (Value::Object(_) | Value::Array(_))) => component,
+        _ => json!({ "text": text }),
+    }
+}
+
+/// Parses `NAME` or `NAME=UUID` into a player sample entry.
+pub fn parse_sample_player(player: &str) -> Result<SamplePlayer, String> {
+    let (name, id) = match player.split_once('=') {
+        Some((name, id)) => (name, id),
+        None => (player, EMPTY_UUID),
+    };
+    if name.is_empty() {
+        return Err(String::from("Sample player name is empty"));
+    }
+    if !is_hyphenated_uuid(id) {
+        return Err(format!("{} is not a hyphenated UUID", id));
+    }
+    Ok(SamplePlayer {
+        name: name.to_string(),
+        id: id.to_lowercase(),
+    })
+}
+
+/// Loads a 64x64 PNG and encodes it as a data URI.
+pub fn load_favicon(path: &Path) -> Result<String, Box<dyn Error>> {
+    let png = std::fs::read(path)?;
+    // The IHDR chunk always comes first, right after the signature.
+    if png.len() < 24 || png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        return Err(Box::from("Favicon is not a PNG image"));
+    }
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(Box::from(format!(
+            "Favicon must be {}x{}, got {}x{}",
+            FAVICON_SIZE, FAVICON_SIZE, width, height
+        )));
+    }
+    Ok(format!("data:image/png;base64,{}", STANDARD.encode(&png)))
+}
+
+fn flatten_component(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(components) => components.iter().map(flatten_component).collect(),
+        Value::Object(fields) => {
+            let mut text = fields
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if let Some(extra) = fields.get("extra") {
+                text.push_str(&flatten_component(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+fn is_hyphenated_uuid(id: &str) -> bool {
+    id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
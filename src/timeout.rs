@@ -0,0 +1,79 @@
+use crate::error::ConnectionError;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Expired penalties are only swept once the list grows past this size, and at most once per interval.
+static PENALTY_SWEEP_THRESHOLD: usize = 4096usize;
+static PENALTY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    pub handshake: Duration,
+    pub status: Duration,
+    pub connection: Duration,
+    pub write: Duration,
+    /// How long a timed out peer is refused for. Zero disables penalties.
+    pub penalty: Duration,
+    pub shutdown_grace: Duration,
+}
+
+/// Runs `future` with a deadline, reporting an expiry as [`ConnectionError::TimedOut`] for `phase`.
+pub async fn within<T, F>(
+    duration: Duration,
+    phase: &'static str,
+    future: F,
+) -> Result<T, ConnectionError>
+where
+    F: Future<Output = Result<T, ConnectionError>>,
+{
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => result,
+        Err(_) => Err(ConnectionError::TimedOut(phase)),
+    }
+}
+
+/// Peers that recently timed out, refused until their penalty expires.
+#[derive(Default)]
+pub struct PenaltyBox {
+    penalized: Mutex<Penalties>,
+}
+
+#[derive(Default)]
+struct Penalties {
+    until: HashMap<IpAddr, Instant>,
+    swept: Option<Instant>,
+}
+
+impl PenaltyBox {
+    pub fn penalize(&self, ip: IpAddr, duration: Duration) {
+        if duration.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut penalized = self.penalized.lock().unwrap();
+        if penalized.until.len() >= PENALTY_SWEEP_THRESHOLD
+            && penalized
+                .swept
+                .is_none_or(|swept| now.duration_since(swept) >= PENALTY_SWEEP_INTERVAL)
+        {
+            penalized.until.retain(|_, until| *until > now);
+            penalized.swept = Some(now);
+        }
+        penalized.until.insert(ip, now + duration);
+    }
+
+    pub fn is_penalized(&self, ip: &IpAddr) -> bool {
+        let mut penalized = self.penalized.lock().unwrap();
+        match penalized.until.get(ip) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                penalized.until.remove(ip);
+                false
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use rolling_glass::{is_known_protocol_number, ProtocolNum};
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+
+// Never matches a client's protocol number, so the server list shows the version name in red.
+static INCOMPATIBLE_PROTOCOL_NUMBER: i32 = -1i32;
+// https://minecraft.wiki/w/Protocol_version_numbers
+// Releases are still far below this, snapshots are numbered from 0x40000000 and aren't named.
+static RELEASE_PROTOCOL_NUMBER_LIMIT: i32 = 4096i32;
+static KNOWN_RELEASES: OnceLock<Option<(i32, i32)>> = OnceLock::new();
+// https://minecraft.wiki/w/Protocol_version_numbers
+// The first and last release of each protocol number, newer ones are named by their number.
+static RELEASE_NAMES: [(i32, &str, &str); 44] = [
+    (4, "1.7.2", "1.7.5"),
+    (5, "1.7.6", "1.7.10"),
+    (47, "1.8", "1.8.9"),
+    (107, "1.9", "1.9"),
+    (108, "1.9.1", "1.9.1"),
+    (109, "1.9.2", "1.9.2"),
+    (110, "1.9.3", "1.9.4"),
+    (210, "1.10", "1.10.2"),
+    (315, "1.11", "1.11"),
+    (316, "1.11.1", "1.11.2"),
+    (335, "1.12", "1.12"),
+    (338, "1.12.1", "1.12.1"),
+    (340, "1.12.2", "1.12.2"),
+    (393, "1.13", "1.13"),
+    (401, "1.13.1", "1.13.1"),
+    (404, "1.13.2", "1.13.2"),
+    (477, "1.14", "1.14"),
+    (480, "1.14.1", "1.14.1"),
+    (485, "1.14.2", "1.14.2"),
+    (490, "1.14.3", "1.14.3"),
+    (498, "1.14.4", "1.14.4"),
+    (573, "1.15", "1.15"),
+    (575, "1.15.1", "1.15.1"),
+    (578, "1.15.2", "1.15.2"),
+    (735, "1.16", "1.16"),
+    (736, "1.16.1", "1.16.1"),
+    (751, "1.16.2", "1.16.2"),
+    (753, "1.16.3", "1.16.3"),
+    (754, "1.16.4", "1.16.5"),
+    (755, "1.17", "1.17"),
+    (756, "1.17.1", "1.17.1"),
+    (757, "1.18", "1.18.1"),
+    (758, "1.18.2", "1.18.2"),
+    (759, "1.19", "1.19"),
+    (760, "1.19.1", "1.19.2"),
+    (761, "1.19.3", "1.19.3"),
+    (762, "1.19.4", "1.19.4"),
+    (763, "1.20", "1.20.1"),
+    (764, "1.20.2", "1.20.2"),
+    (765, "1.20.3", "1.20.4"),
+    (766, "1.20.5", "1.20.6"),
+    (767, "1.21", "1.21.1"),
+    (768, "1.21.2", "1.21.3"),
+    (769, "1.21.4", "1.21.4"),
+];
+
+/// Which protocol numbers get answered. The others are told which versions work instead.
+#[derive(Clone, Debug, Default)]
+pub struct VersionPolicy {
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    /// Answers protocol numbers RollingGlass doesn't know, such as snapshots and newer releases.
+    pub accept_unknown: bool,
+    /// How the supported versions are named to the others. Defaults to the releases at either end of the window,
+    /// bounded by the releases RollingGlass knows where `min` or `max` is unset.
+    pub name: Option<String>,
+}
+
+impl VersionPolicy {
+    pub fn supports(&self, protocol_number: i32) -> bool {
+        (self.accept_unknown || is_known_protocol_number(protocol_number as ProtocolNum))
+            && !matches!(self.min, Some(min) if protocol_number < min)
+            && !matches!(self.max, Some(max) if protocol_number > max)
+    }
+
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        // Without accept_unknown, the releases RollingGlass knows bound the window too.
+        let known = if self.accept_unknown {
+            None
+        } else {
+            known_releases()
+        };
+        let min = self
+            .min
+            .or(known.map(|(min, _)| min))
+            .map(|min| match release(min) {
+                Some((_, first, _)) => first.to_string(),
+                None => format!("protocol {}", min),
+            });
+        let max = self
+            .max
+            .or(known.map(|(_, max)| max))
+            .map(|max| match release(max) {
+                Some((_, _, last)) => last.to_string(),
+                None => format!("protocol {}", max),
+            });
+        match (min, max) {
+            (Some(min), Some(max)) => format!("{} to {}", min, max),
+            (Some(min), None) => format!("{} and later", min),
+            (None, Some(max)) => format!("{} and earlier", max),
+            (None, None) => String::from("any protocol"),
+        }
+    }
+
+    /// Shown as the MOTD and as the Disconnect (Login) message.
+    pub fn message(&self) -> Value {
+        json!({ "text": format!("Unsupported version, this server supports {}", self.name()) })
+    }
+
+    /// Turns a status response into one the client shows as incompatible.
+    pub fn incompatible_status(&self, mut status: Value) -> Value {
+        status["version"] = json!({
+            "name": self.name(),
+            "protocol": INCOMPATIBLE_PROTOCOL_NUMBER,
+        });
+        status["description"] = self.message();
+        status
+    }
+}
+
+/// The lowest and highest release protocol numbers RollingGlass knows.
+fn known_releases() -> Option<(i32, i32)> {
+    *KNOWN_RELEASES.get_or_init(|| {
+        let mut known = (0..RELEASE_PROTOCOL_NUMBER_LIMIT)
+            .filter(|&protocol_number| is_known_protocol_number(protocol_number as ProtocolNum));
+        let min = known.next()?;
+        Some((min, known.last().unwrap_or(min)))
+    })
+}
+
+fn release(protocol_number: i32) -> Option<&'static (i32, &'static str, &'static str)> {
+    RELEASE_NAMES
+        .binary_search_by_key(&protocol_number, |(number, _, _)| *number)
+        .ok()
+        .map(|index| &RELEASE_NAMES[index])
+}
+
+/// The releases speaking `protocol_number`, such as `1.21-1.21.1`.
+pub fn release_name(protocol_number: i32) -> Option<String> {
+    release(protocol_number).map(|(_, first, last)| {
+        if first == last {
+            first.to_string()
+        } else {
+            format!("{}-{}", first, last)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_names() {
+        let policy = VersionPolicy {
+            min: Some(47),
+            max: Some(767),
+            ..VersionPolicy::default()
+        };
+        assert_eq!(policy.name(), "1.8 to 1.21.1");
+        let policy = VersionPolicy {
+            min: Some(48),
+            max: Some(4095),
+            ..VersionPolicy::default()
+        };
+        assert_eq!(policy.name(), "protocol 48 to protocol 4095");
+        let policy = VersionPolicy {
+            min: Some(766),
+            accept_unknown: true,
+            ..VersionPolicy::default()
+        };
+        assert_eq!(policy.name(), "1.20.5 and later");
+        let policy = VersionPolicy {
+            name: Some(String::from("1.8 to 1.21")),
+            ..VersionPolicy::default()
+        };
+        assert_eq!(policy.name(), "1.8 to 1.21");
+    }
+
+    #[test]
+    fn release_names() {
+        assert!(RELEASE_NAMES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(release_name(47).unwrap(), "1.8-1.8.9");
+        assert_eq!(release_name(762).unwrap(), "1.19.4");
+        assert_eq!(release_name(46), None);
+    }
+}
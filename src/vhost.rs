@@ -0,0 +1,263 @@
+use crate::status::{load_favicon, parse_component, parse_sample_player, ShowIp, StatusConfig};
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Everything a connection gets to see, selected by the hostname it connected with.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub status: StatusConfig,
+    pub disconnect_message: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    /// `*.example.com` matches any subdomain of `example.com`, but not `example.com` itself.
+    Wildcard(String),
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Result<Self, Box<dyn Error>> {
+        let pattern = normalize_hostname(pattern);
+        if pattern.is_empty() {
+            return Err(Box::from("Virtual host pattern is empty"));
+        }
+        match pattern.strip_prefix("*.") {
+            Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => {
+                Ok(HostPattern::Wildcard(format!(".{}", suffix)))
+            }
+            Some(_) => Err(Box::from(format!("{} is not a valid wildcard", pattern))),
+            None if pattern.contains('*') => Err(Box::from(format!(
+                "{} may only use a wildcard as its first label",
+                pattern
+            ))),
+            None => Ok(HostPattern::Exact(pattern)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct VirtualHost {
+    patterns: Vec<HostPattern>,
+    profile: Profile,
+}
+
+#[derive(Clone, Debug)]
+pub struct VirtualHosts {
+    hosts: Vec<VirtualHost>,
+    default: Profile,
+}
+
+impl VirtualHosts {
+    pub fn new(default: Profile) -> Self {
+        VirtualHosts {
+            hosts: Vec::new(),
+            default,
+        }
+    }
+
+    pub fn default_profile(&self) -> &Profile {
+        &self.default
+    }
+
+    /// Exact matches win over wildcards, and longer wildcards win over shorter ones.
+    /// `hostname` must already be normalized.
+    pub fn select(&self, hostname: &str) -> &Profile {
+        let mut best: Option<(&Profile, usize)> = None;
+        for host in &self.hosts {
+            for pattern in &host.patterns {
+                match pattern {
+                    HostPattern::Exact(exact) if exact == hostname => return &host.profile,
+                    HostPattern::Wildcard(suffix)
+                        if hostname.ends_with(suffix.as_str())
+                            && best.is_none_or(|(_, len)| suffix.len() > len) =>
+                    {
+                        best = Some((&host.profile, suffix.len()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        best.map(|(profile, _)| profile).unwrap_or(&self.default)
+    }
+
+    /// Loads `[[virtual_host]]` tables, every field but `hosts` falls back to the default profile.
+    pub fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file: VirtualHostsFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+        for entry in file.virtual_host {
+            self.add(entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, entry: VirtualHostEntry) -> Result<(), Box<dyn Error>> {
+        if entry.hosts.is_empty() {
+            return Err(Box::from("Virtual host without hosts"));
+        }
+        let patterns = entry
+            .hosts
+            .iter()
+            .map(|host| HostPattern::parse(host))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut profile = self.default.clone();
+        let status = &mut profile.status;
+        if let Some(brand) = entry.brand {
+            status.brand = brand;
+        }
+        if let Some(motd) = &entry.motd {
+            status.description = parse_component(motd);
+        }
+        if let Some(max_players) = entry.max_players {
+            status.max_players = max_players;
+        }
+        if let Some(online_players) = entry.online_players {
+            status.online_players = online_players;
+        }
+        if let Some(sample_players) = &entry.sample_players {
+            status.sample = sample_players
+                .iter()
+                .map(|player| parse_sample_player(player))
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        if let Some(favicon) = &entry.favicon {
+            status.favicon = Some(load_favicon(favicon)?);
+        }
+        if let Some(enforces_secure_chat) = entry.enforces_secure_chat {
+            status.enforces_secure_chat = enforces_secure_chat;
+        }
+        if let Some(show_ip) = entry.show_ip {
+            status.show_ip = show_ip;
+        }
+        if let Some(disconnect_message) = &entry.disconnect_message {
+            profile.disconnect_message = parse_component(disconnect_message);
+        }
+        self.hosts.push(VirtualHost { patterns, profile });
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VirtualHostsFile {
+    #[serde(default)]
+    virtual_host: Vec<VirtualHostEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHostEntry {
+    pub hosts: Vec<String>,
+    pub brand: Option<String>,
+    pub motd: Option<String>,
+    pub max_players: Option<usize>,
+    pub online_players: Option<usize>,
+    pub sample_players: Option<Vec<String>>,
+    pub favicon: Option<PathBuf>,
+    pub enforces_secure_chat: Option<bool>,
+    pub show_ip: Option<ShowIp>,
+    pub disconnect_message: Option<String>,
+}
+
+/// Lowercases the hostname, and strips the trailing dot of fully qualified names
+/// along with anything past the first NUL, where Forge puts its `\0FML\0` markers.
+pub fn normalize_hostname(hostname: &str) -> String {
+    let hostname = match hostname.split_once('\0') {
+        Some((hostname, _)) => hostname,
+        None => hostname,
+    };
+    hostname.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hosts: &[&str], brand: &str) -> VirtualHostEntry {
+        VirtualHostEntry {
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            brand: Some(brand.to_string()),
+            motd: None,
+            max_players: None,
+            online_players: None,
+            sample_players: None,
+            favicon: None,
+            enforces_secure_chat: None,
+            show_ip: None,
+            disconnect_message: None,
+        }
+    }
+
+    fn virtual_hosts() -> VirtualHosts {
+        let mut virtual_hosts = VirtualHosts::new(Profile {
+            status: StatusConfig::new(String::from("Default")),
+            disconnect_message: parse_component("Default"),
+        });
+        virtual_hosts
+            .add(entry(&["*.example.com"], "Wildcard"))
+            .unwrap();
+        virtual_hosts
+            .add(entry(&["*.eu.example.com"], "Europe"))
+            .unwrap();
+        virtual_hosts
+            .add(entry(&["Play.Example.com."], "Play"))
+            .unwrap();
+        virtual_hosts
+    }
+
+    fn brand<'a>(virtual_hosts: &'a VirtualHosts, hostname: &str) -> &'a str {
+        &virtual_hosts
+            .select(&normalize_hostname(hostname))
+            .status
+            .brand
+    }
+
+    #[test]
+    fn hostnames() {
+        assert_eq!(normalize_hostname("Play.Example.COM"), "play.example.com");
+        assert_eq!(normalize_hostname("play.example.com."), "play.example.com");
+        assert_eq!(
+            normalize_hostname("play.example.com\0FML\0"),
+            "play.example.com"
+        );
+        assert_eq!(
+            normalize_hostname("play.example.com.\0FML3\0"),
+            "play.example.com"
+        );
+        assert_eq!(normalize_hostname(""), "");
+    }
+
+    #[test]
+    fn selection() {
+        let virtual_hosts = virtual_hosts();
+        // Exact matches win over wildcards, even ones added earlier.
+        assert_eq!(brand(&virtual_hosts, "play.example.com"), "Play");
+        assert_eq!(brand(&virtual_hosts, "PLAY.example.com."), "Play");
+        assert_eq!(brand(&virtual_hosts, "play.example.com\0FML2\0"), "Play");
+        assert_eq!(brand(&virtual_hosts, "lobby.example.com"), "Wildcard");
+        // The longest wildcard wins.
+        assert_eq!(brand(&virtual_hosts, "play.eu.example.com"), "Europe");
+        // Wildcards don't match the domain itself, or lookalikes.
+        assert_eq!(brand(&virtual_hosts, "example.com"), "Default");
+        assert_eq!(brand(&virtual_hosts, "badexample.com"), "Default");
+        assert_eq!(brand(&virtual_hosts, "192.0.2.7"), "Default");
+    }
+
+    #[test]
+    fn patterns() {
+        for pattern in [
+            "",
+            ".",
+            "*.",
+            "*.*.example.com",
+            "play.*.com",
+            "*example.com",
+        ] {
+            assert!(HostPattern::parse(pattern).is_err(), "{:?}", pattern);
+        }
+        assert!(VirtualHosts::new(virtual_hosts().default.clone())
+            .add(entry(&[], "Empty"))
+            .is_err());
+    }
+}
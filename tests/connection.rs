@@ -0,0 +1,517 @@
+//! Whole connections, played against the default `Responder` over an in-memory duplex stream.
+
+use rolling_looking_glass::access_log::AccessRecord;
+use rolling_looking_glass::codec::{
+    frame, get_i64, get_string, put_string, put_u16, put_uuid, put_varint,
+};
+use rolling_looking_glass::config::{Config, ConfigFile, DEFAULT_BRAND};
+use rolling_looking_glass::error::ConnectionError;
+use rolling_looking_glass::frame::read_frame;
+use rolling_looking_glass::handler::{Handler, HandshakeContext};
+use rolling_looking_glass::limit::Limiter;
+use rolling_looking_glass::metrics::Metrics;
+use rolling_looking_glass::responder::Responder;
+use rolling_looking_glass::status::{parse_component, StatusConfig};
+use rolling_looking_glass::version::VersionPolicy;
+use rolling_looking_glass::vhost::{Profile, VirtualHostEntry};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, BufReader};
+
+static PROTOCOL_NUMBER: i32 = 767i32;
+static PEER: &str = "192.0.2.1:50000";
+
+fn handshake(protocol_number: i32, hostname: &str, intent: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_varint(&mut body, protocol_number);
+    put_string(&mut body, hostname);
+    put_u16(&mut body, 25565);
+    put_varint(&mut body, intent);
+    frame(0x00, &body)
+}
+
+fn login_start(username: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, username);
+    put_uuid(&mut body, 0x069a79f444e94726a5befca90e38aaf5u128);
+    frame(0x00, &body)
+}
+
+fn ping(payload: i64) -> Vec<u8> {
+    frame(0x01, &payload.to_be_bytes())
+}
+
+fn profile() -> Profile {
+    Profile {
+        status: StatusConfig::new(String::from("Unused")),
+        disconnect_message: parse_component("Unused"),
+    }
+}
+
+/// Reads one packet off what the server wrote, and parses its JSON.
+async fn read_json(output: &mut &[u8]) -> Value {
+    let packet = read_frame(output, usize::MAX, "Packet").await.unwrap();
+    let json = get_string(&mut packet.body(), 262144, "JSON").unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+async fn exchange(input: &[u8]) -> (Result<(), ConnectionError>, Vec<u8>) {
+    let config = Config::build(&ConfigFile::default()).unwrap();
+    exchange_with(&config.listeners[0].responder, input).await
+}
+
+/// Sends everything the client has to say at once, then reads all the server wrote until it let go.
+async fn exchange_with<H: Handler>(
+    responder: &Responder<H>,
+    input: &[u8],
+) -> (Result<(), ConnectionError>, Vec<u8>) {
+    let peer = PEER.parse().unwrap();
+    let (mut client, server) = duplex(64 * 1024);
+    client.write_all(input).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut socket = BufReader::new(server);
+    let result = responder.respond(&mut socket, &peer).await;
+    drop(socket);
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    (result, output)
+}
+
+#[tokio::test]
+async fn status_and_ping() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    input.extend_from_slice(&ping(0x0123456789ABCDEF));
+    let (result, output) = exchange(&input).await;
+    result.unwrap();
+
+    let mut output = output.as_slice();
+    let response = read_frame(&mut output, usize::MAX, "Status Response")
+        .await
+        .unwrap();
+    assert_eq!(response.id, 0x00);
+    let json = get_string(&mut response.body(), 32767, "JSON").unwrap();
+    let json: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["version"]["protocol"], PROTOCOL_NUMBER);
+    assert_eq!(json["version"]["name"], DEFAULT_BRAND);
+
+    let pong = read_frame(&mut output, usize::MAX, "Pong Response")
+        .await
+        .unwrap();
+    assert_eq!(pong.id, 0x01);
+    assert_eq!(get_i64(&mut pong.body()).unwrap(), 0x0123456789ABCDEF);
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn ping_without_status_request() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input.extend_from_slice(&ping(42));
+    let (result, output) = exchange(&input).await;
+    result.unwrap();
+    assert_eq!(output, ping(42));
+}
+
+#[tokio::test]
+async fn login_disconnect() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 2);
+    input.extend_from_slice(&login_start("Notch"));
+    let (result, output) = exchange(&input).await;
+    result.unwrap();
+
+    let mut output = output.as_slice();
+    let disconnect = read_frame(&mut output, usize::MAX, "Disconnect")
+        .await
+        .unwrap();
+    assert_eq!(disconnect.id, 0x00);
+    let json = get_string(&mut disconnect.body(), 262144, "JSON").unwrap();
+    let message: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(message["text"], "Your IP address is 192.0.2.1");
+    assert!(output.is_empty());
+}
+
+fn legacy_string(out: &mut Vec<u8>, value: &str) {
+    let units: Vec<u16> = value.encode_utf16().collect();
+    out.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        out.extend_from_slice(&unit.to_be_bytes());
+    }
+}
+
+/// Decodes the legacy Kick packet the server answered a legacy ping with.
+fn read_kick(output: &[u8]) -> String {
+    assert_eq!(output[0], 0xFF);
+    let units: Vec<u16> = output[3..]
+        .chunks(2)
+        .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+        .collect();
+    String::from_utf16(&units).unwrap()
+}
+
+/// What a 1.6 client sends to list a server.
+fn legacy_ping_host(hostname: &str) -> Vec<u8> {
+    let mut data = vec![78u8];
+    legacy_string(&mut data, hostname);
+    data.extend_from_slice(&25565i32.to_be_bytes());
+    let mut input = vec![0xFE, 0x01, 0xFA];
+    legacy_string(&mut input, "MC|PingHost");
+    input.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    input.extend_from_slice(&data);
+    input
+}
+
+#[tokio::test]
+async fn legacy_ping_virtual_host() {
+    let mut responder = Responder::new(profile());
+    responder
+        .virtual_hosts
+        .add(VirtualHostEntry {
+            hosts: vec![String::from("play.example.com")],
+            brand: Some(String::from("Play")),
+            motd: None,
+            max_players: None,
+            online_players: None,
+            sample_players: None,
+            favicon: None,
+            enforces_secure_chat: None,
+            show_ip: None,
+            disconnect_message: None,
+        })
+        .unwrap();
+
+    let (result, output) = exchange_with(&responder, &legacy_ping_host("Play.Example.com.")).await;
+    result.unwrap();
+    assert_eq!(read_kick(&output).split('\0').nth(2), Some("Play"));
+
+    let (result, output) = exchange_with(&responder, &legacy_ping_host("localhost")).await;
+    result.unwrap();
+    assert_eq!(read_kick(&output).split('\0').nth(2), Some("Unused"));
+}
+
+#[tokio::test]
+async fn version_name_placeholder() {
+    let responder = Responder::new(Profile {
+        status: StatusConfig::new(String::from("Unused")),
+        disconnect_message: parse_component("{version_name} is protocol {protocol}"),
+    });
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 2);
+    input.extend_from_slice(&login_start("Notch"));
+    let (result, output) = exchange_with(&responder, &input).await;
+    result.unwrap();
+    assert_eq!(
+        read_json(&mut output.as_slice()).await["text"],
+        "1.21-1.21.1 is protocol 767"
+    );
+}
+
+#[tokio::test]
+async fn oversized_handshake() {
+    let mut input = Vec::new();
+    put_varint(&mut input, 1 << 20);
+    input.extend_from_slice(&[0u8; 64]);
+    let (result, output) = exchange(&input).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::TooLong {
+            what: "Handshake packet",
+            ..
+        })
+    ));
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn oversized_server_address() {
+    let hostname = "a".repeat(256);
+    let (result, _) = exchange(&handshake(PROTOCOL_NUMBER, &hostname, 1)).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::TooLong {
+            what: "Server address",
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn bad_packet_id() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input[1] = 0x05;
+    let (result, output) = exchange(&input).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::UnknownPacketId { id: 0x05, .. })
+    ));
+    assert!(output.is_empty());
+
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input.extend_from_slice(&frame(0x07, &[]));
+    let (result, output) = exchange(&input).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::UnknownPacketId { id: 0x07, .. })
+    ));
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn negative_protocol() {
+    let (result, output) = exchange(&handshake(-1, "localhost", 1)).await;
+    assert!(matches!(result, Err(ConnectionError::UnknownProtocol(-1))));
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_protocol() {
+    let name = VersionPolicy::default().name();
+    assert!(name.contains(" to "));
+    let message = format!("Unsupported version, this server supports {}", name);
+    let mut input = handshake(0x7FFFFFFF, "localhost", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    input.extend_from_slice(&ping(42));
+    let (result, output) = exchange(&input).await;
+    result.unwrap();
+    let mut output = output.as_slice();
+    let json = read_json(&mut output).await;
+    assert_eq!(json["version"]["protocol"], -1);
+    assert_eq!(json["version"]["name"], name.as_str());
+    assert_eq!(json["description"]["text"], message);
+    assert_eq!(output, ping(42));
+
+    // Login Start isn't needed to tell the client off.
+    let (result, output) = exchange(&handshake(0x7FFFFFFF, "localhost", 2)).await;
+    result.unwrap();
+    let mut output = output.as_slice();
+    assert_eq!(read_json(&mut output).await["text"], message);
+    assert!(output.is_empty());
+}
+
+/// Plays `input` against a connection over a limit.
+async fn refuse(input: &[u8]) -> (Result<(), ConnectionError>, Vec<u8>) {
+    let responder = Responder::new(profile());
+    let peer = PEER.parse().unwrap();
+    let (mut client, server) = duplex(64 * 1024);
+    client.write_all(input).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut socket = BufReader::new(server);
+    let result = responder
+        .refuse(&mut socket, &peer, r#"{"text":"Slow down"}"#)
+        .await;
+    drop(socket);
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    (result, output)
+}
+
+#[tokio::test]
+async fn refuse_status() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    let (result, output) = refuse(&input).await;
+    result.unwrap();
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn refuse_login() {
+    let (result, output) = refuse(&handshake(PROTOCOL_NUMBER, "localhost", 2)).await;
+    result.unwrap();
+    let mut output = output.as_slice();
+    assert_eq!(read_json(&mut output).await["text"], "Slow down");
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn refuse_transfer() {
+    let (result, output) = refuse(&handshake(PROTOCOL_NUMBER, "localhost", 3)).await;
+    result.unwrap();
+    let mut output = output.as_slice();
+    assert_eq!(read_json(&mut output).await["text"], "Slow down");
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn refuse_oversized_handshake() {
+    let input = handshake(PROTOCOL_NUMBER, &"a".repeat(1024), 2);
+    let (result, output) = refuse(&input).await;
+    assert!(matches!(result, Err(ConnectionError::TooLong { .. })));
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn unsupported_protocol_metrics() {
+    let responder = Responder::new(profile());
+    let metrics = Metrics::default();
+    let series = |metrics: &Metrics| {
+        metrics
+            .render(&Limiter::default())
+            .lines()
+            .filter(|line| line.starts_with("rolling_looking_glass_protocol_versions_total"))
+            .count()
+    };
+    let before = series(&metrics);
+    for protocol_number in [0x7FFFFFFE, 0x7FFFFFFF] {
+        let peer = PEER.parse().unwrap();
+        let (mut client, server) = duplex(64 * 1024);
+        client
+            .write_all(&handshake(protocol_number, "localhost", 2))
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let mut record = AccessRecord::new(0, String::new(), &peer);
+        responder
+            .respond_with(
+                &mut BufReader::new(server),
+                &peer,
+                None,
+                &metrics,
+                &mut record,
+            )
+            .await
+            .unwrap();
+    }
+    let rendered = metrics.render(&Limiter::default());
+    assert_eq!(series(&metrics), before);
+    assert!(rendered
+        .contains("rolling_looking_glass_protocol_versions_total{protocol=\"unsupported\"} 2"));
+}
+
+#[tokio::test]
+async fn protocol_window() {
+    let mut responder = Responder::new(profile());
+    responder.versions = VersionPolicy {
+        min: Some(47),
+        max: Some(PROTOCOL_NUMBER - 1),
+        ..VersionPolicy::default()
+    };
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    let (_, output) = exchange_with(&responder, &input).await;
+    let json = read_json(&mut output.as_slice()).await;
+    assert_eq!(json["version"]["protocol"], -1);
+    assert_eq!(json["version"]["name"], "1.8 to 1.20.6");
+
+    let mut input = handshake(46, "localhost", 2);
+    input.extend_from_slice(&login_start("Notch"));
+    let (result, output) = exchange_with(&responder, &input).await;
+    result.unwrap();
+    assert_eq!(
+        read_json(&mut output.as_slice()).await["text"],
+        "Unsupported version, this server supports 1.8 to 1.20.6"
+    );
+
+    responder.versions = VersionPolicy {
+        accept_unknown: true,
+        ..VersionPolicy::default()
+    };
+    let mut input = handshake(0x7FFFFFFF, "localhost", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    let (_, output) = exchange_with(&responder, &input).await;
+    let json = read_json(&mut output.as_slice()).await;
+    assert_eq!(json["version"]["protocol"], 0x7FFFFFFF);
+}
+
+#[tokio::test]
+async fn bad_intent() {
+    for intent in [0, 4, -1] {
+        let (result, output) = exchange(&handshake(PROTOCOL_NUMBER, "localhost", intent)).await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidIntent(number)) if number == intent
+        ));
+        assert!(output.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn overlong_username() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 2);
+    input.extend_from_slice(&login_start("ThisNameIsTooLong"));
+    let (result, output) = exchange(&input).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::TooLong {
+            what: "Username",
+            ..
+        })
+    ));
+    assert!(output.is_empty());
+
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 2);
+    input.extend_from_slice(&login_start(""));
+    let (result, _) = exchange(&input).await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::InvalidUsernameLength(0))
+    ));
+}
+
+#[tokio::test]
+async fn truncated_input() {
+    let mut input = handshake(PROTOCOL_NUMBER, "localhost", 2);
+    input.extend_from_slice(&login_start("Notch"));
+    // Every strict prefix of a valid login ends with the client going away.
+    for length in 0..input.len() {
+        let (result, output) = exchange(&input[..length]).await;
+        assert!(
+            matches!(result, Err(ConnectionError::Disconnected)),
+            "prefix of {} bytes",
+            length
+        );
+        assert!(output.is_empty());
+    }
+}
+
+/// Counts the players it has seen, and greets them by name.
+#[derive(Default)]
+struct Greeter {
+    seen: AtomicUsize,
+}
+
+impl Handler for Greeter {
+    async fn status(&self, context: &HandshakeContext<'_>) -> Value {
+        json!({
+            "version": { "name": context.hostname, "protocol": context.protocol_number },
+            "players": { "max": 0, "online": self.seen.load(Ordering::Relaxed) },
+        })
+    }
+
+    async fn disconnect(&self, context: &HandshakeContext<'_>) -> Value {
+        self.seen.fetch_add(1, Ordering::Relaxed);
+        json!({ "text": format!("Hello {}", context.username.unwrap()) })
+    }
+}
+
+#[tokio::test]
+async fn custom_handler() {
+    let responder = Responder::new(profile()).with_handler(Greeter::default());
+
+    let mut input = handshake(PROTOCOL_NUMBER, "example.com", 2);
+    input.extend_from_slice(&login_start("Notch"));
+    let (result, output) = exchange_with(&responder, &input).await;
+    result.unwrap();
+    let mut output = output.as_slice();
+    let disconnect = read_frame(&mut output, usize::MAX, "Disconnect")
+        .await
+        .unwrap();
+    let json = get_string(&mut disconnect.body(), 262144, "JSON").unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&json).unwrap(),
+        json!({ "text": "Hello Notch" })
+    );
+
+    let mut input = handshake(PROTOCOL_NUMBER, "example.com", 1);
+    input.extend_from_slice(&frame(0x00, &[]));
+    let (result, output) = exchange_with(&responder, &input).await;
+    assert!(matches!(result, Err(ConnectionError::Disconnected)));
+    let mut output = output.as_slice();
+    let response = read_frame(&mut output, usize::MAX, "Status Response")
+        .await
+        .unwrap();
+    let json = get_string(&mut response.body(), 32767, "JSON").unwrap();
+    let json: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["version"]["name"], "example.com");
+    assert_eq!(json["players"]["online"], 1);
+}
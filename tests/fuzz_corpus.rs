@@ -0,0 +1,45 @@
+//! Replays the fuzzing corpora. Crashes and hangs found by `cargo fuzz` go into them once fixed,
+//! so each one stays a regression test.
+
+#[path = "../fuzz/fuzz_targets/harness.rs"]
+mod harness;
+
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+fn replay(target: &str, check: fn(&[u8])) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz")
+        .join("corpus")
+        .join(target);
+    let mut replayed = 0;
+    for entry in fs::read_dir(&directory).unwrap() {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        let result = panic::catch_unwind(|| check(&data));
+        assert!(result.is_ok(), "{} failed", path.display());
+        replayed += 1;
+    }
+    assert!(replayed > 0, "{} is empty", directory.display());
+}
+
+#[test]
+fn handshake() {
+    replay("handshake", harness::handshake);
+}
+
+#[test]
+fn read_varint() {
+    replay("read_varint", harness::read_varint);
+}
+
+#[test]
+fn status_ping() {
+    replay("status_ping", harness::status_ping);
+}
+
+#[test]
+fn login_start() {
+    replay("login_start", harness::login_start);
+}